@@ -0,0 +1,98 @@
+use crate::event::Event;
+use crate::id::Id;
+use crate::params::ParamsError;
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimError {
+    // The simulation state refers to a participant which does not exist.
+    MissingParticipant {
+        time: usize,
+        participant_id: Id,
+    },
+    // A delegator refers to a delegatee which does not exist.
+    MissingDelegatee {
+        time: usize,
+        participant_id: Id,
+        delegatee_id: Id,
+    },
+    // The stake change computed for a participant is NaN or infinite.
+    NonFiniteStakeChange {
+        time: usize,
+        participant_id: Id,
+    },
+    // An event consumer received an event inconsistent with the events before it.
+    InvalidEvent {
+        event: Event,
+        reason: String,
+    },
+    // The scheduled params are invalid at this time.
+    InvalidParams {
+        time: usize,
+        error: ParamsError,
+    },
+    // An event consumer failed to write its output.
+    Io {
+        kind: io::ErrorKind,
+        message: String,
+    },
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingParticipant {
+                time,
+                participant_id,
+            } => write!(
+                f,
+                "time {}: participant {:?} does not exist",
+                time, participant_id
+            ),
+            Self::MissingDelegatee {
+                time,
+                participant_id,
+                delegatee_id,
+            } => write!(
+                f,
+                "time {}: participant {:?} delegates to {:?} which does not exist",
+                time, participant_id, delegatee_id
+            ),
+            Self::NonFiniteStakeChange {
+                time,
+                participant_id,
+            } => write!(
+                f,
+                "time {}: stake change of participant {:?} is not a finite number",
+                time, participant_id
+            ),
+            Self::InvalidEvent { event, reason } => {
+                write!(f, "time {}: {} in event {:?}", event.time, reason, event)
+            }
+            Self::InvalidParams { time, error } => write!(f, "time {}: {}", time, error),
+            Self::Io { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SimError {}
+
+impl From<SimError> for io::Error {
+    fn from(e: SimError) -> Self {
+        match e {
+            SimError::Io { kind, message } => io::Error::new(kind, message),
+            e => io::Error::new(io::ErrorKind::Other, e),
+        }
+    }
+}
+
+impl From<io::Error> for SimError {
+    fn from(e: io::Error) -> Self {
+        Self::Io {
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+}
@@ -1,27 +1,67 @@
+use crate::error::SimError;
 use crate::id::Id;
 use crate::role::Role;
+use crate::strategy::{Arm, StrategyKind};
 
+use serde::{Deserialize, Serialize};
+
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fs::File;
+use std::hash::BuildHasherDefault;
 use std::io::Write;
 use std::path::Path;
 
+// Consumers return an error if the event is inconsistent with the ones before it;
+// the simulation stops at the first error.
 pub trait EventConsumer {
-    fn push(&mut self, event: Event);
+    fn push(&mut self, event: Event) -> Result<(), SimError>;
+}
+
+// Absent consumers ignore all events.
+impl<T: EventConsumer> EventConsumer for Option<T> {
+    fn push(&mut self, event: Event) -> Result<(), SimError> {
+        match self {
+            Some(consumer) => consumer.push(event),
+            None => Ok(()),
+        }
+    }
+}
+
+// Both consumers receive every event.
+impl<A: EventConsumer, B: EventConsumer> EventConsumer for (A, B) {
+    fn push(&mut self, event: Event) -> Result<(), SimError> {
+        self.0.push(event.clone())?;
+        self.1.push(event)
+    }
 }
 
-#[derive(Debug)]
+impl<A: EventConsumer, B: EventConsumer, C: EventConsumer> EventConsumer for (A, B, C) {
+    fn push(&mut self, event: Event) -> Result<(), SimError> {
+        self.0.push(event.clone())?;
+        self.1.push(event.clone())?;
+        self.2.push(event)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Event {
     pub time: usize,
     pub info: Info,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Info {
-    // New participant introduced. Their initial role is always `None`.
+    // New participant introduced. Their initial role is always `None`; participants
+    // present at the start may be given a role by a `RoleChange` at time 0. Participants
+    // created by a merge (split) keep the strategy of the first (original) participant.
     ParticipantCreated {
         participant_id: Id,
         num_tokens: f64,
+        // missing in JSON logs written before strategies were recorded
+        #[serde(default)]
+        strategy: StrategyKind,
     },
     StakeChange {
         participant_id: Id,
@@ -42,36 +82,103 @@ pub enum Info {
         participant_id: Id,
         new_participant_ids: (Id, Id),
     },
-    // Participants with less than or equal to 0 tokens are
-    // removed. This is occurrence is recorded by this event.
+    // Participants whose stake runs out without any liquid or unstaking
+    // tokens left are removed. This is occurrence is recorded by this event.
     ParticipantBankrupt {
         participant_id: Id,
     },
+    // A producer did not produce all the blocks (chunks) assigned to it in an epoch.
+    ProductionMissed {
+        participant_id: Id,
+        num_assigned: u64,
+        num_missed: u64,
+    },
+    // A producer produced less than `online_threshold` of its assigned blocks (chunks)
+    // in an epoch. It is followed by a `RoleChange` removing its seat.
+    KickedOut {
+        participant_id: Id,
+    },
+    // A producer misbehaved; `amount` tokens are burned from it and
+    // `delegator_amounts` from each of its delegators.
+    Slashed {
+        participant_id: Id,
+        amount: f64,
+        delegator_amounts: Vec<(Id, f64)>,
+    },
+    // Value estimates of a participant with the `bandit` strategy at the end of an epoch.
+    ValueEstimates {
+        participant_id: Id,
+        values: Vec<(Arm, f64)>,
+    },
+    // A COP was moved to another shard. COPs start out in shard 0.
+    ShardAssigned {
+        participant_id: Id,
+        shard: usize,
+    },
+    // The liquid (unstaked) balance of a participant changed, through the external yield
+    // or by moving tokens from or to its stake, which is recorded by a `StakeChange`.
+    LiquidChange {
+        participant_id: Id,
+        change_amount: f64,
+    },
+    // Tokens of a participant were unstaked and are locked for `unstake_delay` epochs. It
+    // is followed by a `StakeChange` removing them from the stake.
+    UnstakeRequested {
+        participant_id: Id,
+        amount: f64,
+    },
+    // Unstaked tokens were unlocked. It is followed by a `StakeChange` or a `LiquidChange`,
+    // depending on where the tokens go.
+    Withdrawn {
+        participant_id: Id,
+        amount: f64,
+    },
+    // Token flows of an epoch if the rewards come from inflation. `burned` includes the
+    // slashed tokens, `treasury` the part of the reward pool which was not paid out;
+    // `total_supply` is the supply at the end of the epoch.
+    SupplyChange {
+        total_supply: f64,
+        minted: f64,
+        treasury: f64,
+        burned: f64,
+        reward_pool: f64,
+    },
 }
 
+#[allow(dead_code)]
 #[derive(Default)]
 pub struct EventAccumulator {
     pub events: Vec<Event>,
 }
 
 impl EventConsumer for EventAccumulator {
-    fn push(&mut self, event: Event) {
+    fn push(&mut self, event: Event) -> Result<(), SimError> {
         self.events.push(event);
+        Ok(())
     }
 }
 
+#[allow(dead_code)]
 pub struct EventBlackHole;
 
 impl EventConsumer for EventBlackHole {
-    fn push(&mut self, _event: Event) {}
+    fn push(&mut self, _event: Event) -> Result<(), SimError> {
+        Ok(())
+    }
 }
 
+// Do not use RandomState so totals are summed in the same order on every run
+type DeterministicMap<K, V> = HashMap<K, V, BuildHasherDefault<DefaultHasher>>;
+
 #[derive(Default)]
 pub struct StatsAccumulator {
     history: Vec<Stats>,
     current: Stats,
-    stakes: HashMap<Id, f64>,
-    roles: HashMap<Id, Role>,
+    stakes: DeterministicMap<Id, f64>,
+    roles: DeterministicMap<Id, Role>,
+    strategies: DeterministicMap<Id, StrategyKind>,
+    liquid: DeterministicMap<Id, f64>,
+    in_transit: DeterministicMap<Id, f64>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -81,70 +188,218 @@ pub struct Stats {
     total_cop_stake: f64,
     total_delegated_bp_stake: f64,
     total_delegated_cop_stake: f64,
+    // totals since the start of the run
+    missed_blocks: f64,
+    missed_chunks: f64,
+    kickouts: f64,
+    slashed_tokens: f64,
+    // 0 unless the rewards come from inflation
+    total_supply: f64,
+    total_liquid: f64,
+    // tokens which were unstaked and are not withdrawn yet
+    stake_in_transit: f64,
+    // BP and COP stake over all tokens of the participants, staked or not
+    staking_ratio: f64,
+    // BP (COP) stake, including delegated stake, by the strategy of its owner
+    strategy_bp_stake: [f64; StrategyKind::ALL.len()],
+    strategy_cop_stake: [f64; StrategyKind::ALL.len()],
+}
+
+impl Stats {
+    const TOTAL_COLUMNS: &'static [&'static str] = &[
+        "total_bp_stake",
+        "total_cop_stake",
+        "total_delegated_bp_stake",
+        "total_delegated_cop_stake",
+        "missed_blocks",
+        "missed_chunks",
+        "kickouts",
+        "slashed_tokens",
+        "total_supply",
+        "total_liquid",
+        "stake_in_transit",
+        "staking_ratio",
+    ];
+
+    // Names of the values returned by `Stats::values` (excluding time).
+    pub fn columns() -> Vec<String> {
+        let mut columns: Vec<String> = Self::TOTAL_COLUMNS.iter().map(|c| c.to_string()).collect();
+        for strategy in StrategyKind::ALL.iter() {
+            columns.push(format!("{}_bp_stake", strategy.name()));
+            columns.push(format!("{}_cop_stake", strategy.name()));
+        }
+        columns
+    }
+
+    pub fn values(&self) -> Vec<f64> {
+        let mut values = vec![
+            self.total_bp_stake,
+            self.total_cop_stake,
+            self.total_delegated_bp_stake,
+            self.total_delegated_cop_stake,
+            self.missed_blocks,
+            self.missed_chunks,
+            self.kickouts,
+            self.slashed_tokens,
+            self.total_supply,
+            self.total_liquid,
+            self.stake_in_transit,
+            self.staking_ratio,
+        ];
+        for (bp_stake, cop_stake) in self
+            .strategy_bp_stake
+            .iter()
+            .zip(self.strategy_cop_stake.iter())
+        {
+            values.push(*bp_stake);
+            values.push(*cop_stake);
+        }
+        values
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut object = serde_json::Map::new();
+        object.insert("time".to_string(), self.time.into());
+        for (column, value) in Self::columns().into_iter().zip(self.values()) {
+            object.insert(column, value.into());
+        }
+        object.into()
+    }
+
+    fn values_csv(&self) -> String {
+        let values: Vec<String> = self.values().iter().map(|v| v.to_string()).collect();
+        values.join(",")
+    }
 }
 
 impl StatsAccumulator {
     pub fn write_stats<P: AsRef<Path>>(&mut self, file_name: P) -> std::io::Result<()> {
         let mut file = File::create(file_name)?;
-        file.write(b"time,total_bp_stake,total_cop_stake,total_delegated_bp_stake,total_delegated_cop_stake\n")?;
-        for s in self.history.iter() {
-            let line = format!(
-                "{},{},{},{},{}\n",
-                s.time,
-                s.total_bp_stake,
-                s.total_cop_stake,
-                s.total_delegated_bp_stake,
-                s.total_delegated_cop_stake
-            );
-            file.write(&line.as_bytes())?;
+        file.write_all(format!("time,{}\n", Stats::columns().join(",")).as_bytes())?;
+        self.compute_totals();
+        for s in self.history.iter().chain(std::iter::once(&self.current)) {
+            file.write_all(format!("{},{}\n", s.time, s.values_csv()).as_bytes())?;
         }
+        Ok(())
+    }
+
+    // Same content as `write_stats`, as a JSON array with one object per time.
+    pub fn write_stats_json<P: AsRef<Path>>(&mut self, file_name: P) -> std::io::Result<()> {
         self.compute_totals();
-        let line = format!(
-            "{},{},{},{},{}\n",
-            self.current.time,
-            self.current.total_bp_stake,
-            self.current.total_cop_stake,
-            self.current.total_delegated_bp_stake,
-            self.current.total_delegated_cop_stake
-        );
-        file.write(&line.as_bytes())?;
+        let rows: Vec<serde_json::Value> = self
+            .history
+            .iter()
+            .chain(std::iter::once(&self.current))
+            .map(Stats::to_json)
+            .collect();
+        serde_json::to_writer(File::create(file_name)?, &rows)?;
         Ok(())
     }
 
+    // Stats at times `0, interval, 2 * interval, ...` before `duration`. Values are
+    // carried forward over times where no events happened.
+    pub fn sampled_series(&mut self, duration: usize, interval: usize) -> Vec<Stats> {
+        self.compute_totals();
+        let all: Vec<&Stats> = self
+            .history
+            .iter()
+            .chain(std::iter::once(&self.current))
+            .collect();
+        let mut idx = 0;
+        (0..duration)
+            .step_by(interval)
+            .map(|time| {
+                while idx + 1 < all.len() && all[idx + 1].time <= time {
+                    idx += 1;
+                }
+                let mut s = all[idx].clone();
+                s.time = time;
+                s
+            })
+            .collect()
+    }
+
+    // Earliest time from which the ratio of COP stake to BP stake stays within
+    // `tolerance` (relative) of its final value. `None` if nothing was recorded.
+    pub fn convergence_time(&mut self, tolerance: f64) -> Option<usize> {
+        self.compute_totals();
+        let fraction = |s: &Stats| s.total_cop_stake / s.total_bp_stake;
+        let final_fraction = fraction(&self.current);
+        let mut result = None;
+        for s in self
+            .history
+            .iter()
+            .chain(std::iter::once(&self.current))
+            .rev()
+        {
+            let f = fraction(s);
+            // also treats two NaN values (no BP stake) as converged
+            let converged = (f - final_fraction).abs() <= tolerance * final_fraction.abs()
+                || (f.is_nan() && final_fraction.is_nan());
+            if !converged {
+                break;
+            }
+            result = Some(s.time);
+        }
+        result
+    }
+
     fn compute_totals(&mut self) {
         self.current.total_bp_stake = 0.0;
         self.current.total_cop_stake = 0.0;
         self.current.total_delegated_bp_stake = 0.0;
         self.current.total_delegated_cop_stake = 0.0;
+        self.current.strategy_bp_stake = Default::default();
+        self.current.strategy_cop_stake = Default::default();
+        // an empty `sum` of floats is -0
+        self.current.total_liquid = self.liquid.values().fold(0.0, |total, x| total + x);
+        self.current.stake_in_transit = self.in_transit.values().fold(0.0, |total, x| total + x);
+        let mut total_tokens = self.current.total_liquid + self.current.stake_in_transit;
 
         for (id, stake) in self.stakes.iter() {
+            total_tokens += stake;
+            let strategy = self.strategies.get(id).copied().unwrap_or_default().index();
             if let Some(role) = self.roles.get(id) {
                 match role {
-                    Role::BlockProducer => self.current.total_bp_stake += stake,
-                    Role::ChunkOnlyProducer => self.current.total_cop_stake += stake,
+                    Role::BlockProducer => {
+                        self.current.total_bp_stake += stake;
+                        self.current.strategy_bp_stake[strategy] += stake;
+                    }
+                    Role::ChunkOnlyProducer => {
+                        self.current.total_cop_stake += stake;
+                        self.current.strategy_cop_stake[strategy] += stake;
+                    }
                     Role::Delegator(delegatee_id) => match self.roles.get(delegatee_id) {
                         Some(Role::BlockProducer) => {
                             self.current.total_bp_stake += stake;
                             self.current.total_delegated_bp_stake += stake;
+                            self.current.strategy_bp_stake[strategy] += stake;
                         }
                         Some(Role::ChunkOnlyProducer) => {
                             self.current.total_cop_stake += stake;
                             self.current.total_delegated_cop_stake += stake;
+                            self.current.strategy_cop_stake[strategy] += stake;
                         }
                         None | Some(Role::Delegator(_)) => (),
                     },
                 }
             }
         }
+        let total_stake = self.current.total_bp_stake + self.current.total_cop_stake;
+        self.current.staking_ratio = if total_tokens > 0.0 {
+            total_stake / total_tokens
+        } else {
+            0.0
+        };
     }
 
     fn remove_stake_or_default(&mut self, participant_id: &Id) -> f64 {
-        self.stakes.remove(&participant_id).unwrap_or(0.0)
+        self.stakes.remove(participant_id).unwrap_or(0.0)
     }
 }
 
 impl EventConsumer for StatsAccumulator {
-    fn push(&mut self, e: Event) {
+    fn push(&mut self, e: Event) -> Result<(), SimError> {
         if e.time != self.current.time {
             self.compute_totals();
             self.history.push(self.current.clone());
@@ -155,18 +410,23 @@ impl EventConsumer for StatsAccumulator {
             Info::ParticipantCreated {
                 participant_id,
                 num_tokens,
+                strategy,
             } => {
                 self.stakes.insert(participant_id, num_tokens);
+                self.strategies.insert(participant_id, strategy);
             }
             Info::StakeChange {
                 participant_id,
                 change_amount,
-            } => {
-                *self
-                    .stakes
-                    .get_mut(&participant_id)
-                    .expect("Participant must be created before stake is changed") += change_amount;
-            }
+            } => match self.stakes.get_mut(&participant_id) {
+                Some(stake) => *stake += change_amount,
+                None => {
+                    return Err(SimError::InvalidEvent {
+                        event: e,
+                        reason: "stake change of a participant which was never created".to_string(),
+                    })
+                }
+            },
             Info::RoleChange {
                 participant_id,
                 new_role,
@@ -191,6 +451,23 @@ impl EventConsumer for StatsAccumulator {
                 if let Some(role) = role0 {
                     self.roles.insert(new_participant_id, role);
                 }
+                let strategy = self.strategies.remove(&participant_ids.0);
+                self.strategies.remove(&participant_ids.1);
+                if let Some(strategy) = strategy {
+                    self.strategies.insert(new_participant_id, strategy);
+                }
+                let liquid0 = self.liquid.remove(&participant_ids.0);
+                let liquid1 = self.liquid.remove(&participant_ids.1);
+                if liquid0.is_some() || liquid1.is_some() {
+                    let liquid = liquid0.unwrap_or(0.0) + liquid1.unwrap_or(0.0);
+                    self.liquid.insert(new_participant_id, liquid);
+                }
+                let in_transit0 = self.in_transit.remove(&participant_ids.0);
+                let in_transit1 = self.in_transit.remove(&participant_ids.1);
+                if in_transit0.is_some() || in_transit1.is_some() {
+                    let in_transit = in_transit0.unwrap_or(0.0) + in_transit1.unwrap_or(0.0);
+                    self.in_transit.insert(new_participant_id, in_transit);
+                }
             }
             Info::ParticipantSplit {
                 participant_id,
@@ -204,10 +481,209 @@ impl EventConsumer for StatsAccumulator {
                     self.stakes.insert(new_participant_ids.0, stake / 2.0);
                     self.stakes.insert(new_participant_ids.1, stake / 2.0);
                 }
+                if let Some(strategy) = self.strategies.remove(&participant_id) {
+                    self.strategies.insert(new_participant_ids.0, strategy);
+                    self.strategies.insert(new_participant_ids.1, strategy);
+                }
+                if let Some(liquid) = self.liquid.remove(&participant_id) {
+                    self.liquid.insert(new_participant_ids.0, liquid / 2.0);
+                    self.liquid.insert(new_participant_ids.1, liquid / 2.0);
+                }
+                if let Some(in_transit) = self.in_transit.remove(&participant_id) {
+                    self.in_transit
+                        .insert(new_participant_ids.0, in_transit / 2.0);
+                    self.in_transit
+                        .insert(new_participant_ids.1, in_transit / 2.0);
+                }
             }
             Info::ParticipantBankrupt { participant_id } => {
+                self.stakes.remove(&participant_id);
                 self.roles.remove(&participant_id);
+                self.strategies.remove(&participant_id);
+                self.liquid.remove(&participant_id);
+                self.in_transit.remove(&participant_id);
             }
+            Info::UnstakeRequested {
+                participant_id,
+                amount,
+            } => *self.in_transit.entry(participant_id).or_insert(0.0) += amount,
+            Info::Withdrawn {
+                participant_id,
+                amount,
+            } => *self.in_transit.entry(participant_id).or_insert(0.0) -= amount,
+            Info::LiquidChange {
+                participant_id,
+                change_amount,
+            } => *self.liquid.entry(participant_id).or_insert(0.0) += change_amount,
+            Info::ProductionMissed {
+                participant_id,
+                num_missed,
+                ..
+            } => match self.roles.get(&participant_id) {
+                Some(Role::BlockProducer) => self.current.missed_blocks += num_missed as f64,
+                Some(Role::ChunkOnlyProducer) => self.current.missed_chunks += num_missed as f64,
+                None | Some(Role::Delegator(_)) => {
+                    return Err(SimError::InvalidEvent {
+                        event: e,
+                        reason: "missed production of a participant which is not a producer"
+                            .to_string(),
+                    })
+                }
+            },
+            Info::KickedOut { .. } => self.current.kickouts += 1.0,
+            Info::ValueEstimates { .. } | Info::ShardAssigned { .. } => (),
+            Info::SupplyChange { total_supply, .. } => self.current.total_supply = total_supply,
+            Info::Slashed {
+                participant_id,
+                amount,
+                ref delegator_amounts,
+            } => {
+                let own_amount = (participant_id, amount);
+                for (id, amount) in std::iter::once(&own_amount).chain(delegator_amounts.iter()) {
+                    match self.stakes.get_mut(id) {
+                        Some(stake) => *stake -= amount,
+                        None => {
+                            return Err(SimError::InvalidEvent {
+                                event: e.clone(),
+                                reason: format!("participant {:?} was never created", id),
+                            })
+                        }
+                    }
+                    self.current.slashed_tokens += amount;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, EventConsumer, Info, Stats, StatsAccumulator};
+    use crate::error::SimError;
+    use crate::id::Id;
+    use crate::role::Role;
+    use crate::strategy::StrategyKind;
+
+    #[test]
+    fn test_stake_change_of_unknown_participant() {
+        let mut stats = StatsAccumulator::default();
+        let created = Event {
+            time: 0,
+            info: Info::ParticipantCreated {
+                participant_id: Id::explicit(0),
+                num_tokens: 10.0,
+                strategy: StrategyKind::default(),
+            },
+        };
+        let unknown = Event {
+            time: 1,
+            info: Info::StakeChange {
+                participant_id: Id::explicit(1),
+                change_amount: 1.0,
+            },
+        };
+        assert!(stats.push(created).is_ok());
+        match stats.push(unknown.clone()) {
+            Err(SimError::InvalidEvent { event, .. }) => assert_eq!(event, unknown),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missed_production_is_counted() {
+        let mut stats = StatsAccumulator::default();
+        let bp = Id::explicit(0);
+        let infos = vec![
+            Info::ParticipantCreated {
+                participant_id: bp,
+                num_tokens: 10.0,
+                strategy: StrategyKind::default(),
+            },
+            Info::RoleChange {
+                participant_id: bp,
+                new_role: Some(Role::BlockProducer),
+            },
+            Info::ProductionMissed {
+                participant_id: bp,
+                num_assigned: 10,
+                num_missed: 3,
+            },
+            Info::KickedOut { participant_id: bp },
+            Info::RoleChange {
+                participant_id: bp,
+                new_role: None,
+            },
+        ];
+        for info in infos {
+            stats.push(Event { time: 0, info }).unwrap();
+        }
+        let series = stats.sampled_series(1, 1);
+        assert_eq!(series[0].missed_blocks, 3.0);
+        assert_eq!(series[0].missed_chunks, 0.0);
+        assert_eq!(series[0].kickouts, 1.0);
+
+        // only producers can miss production
+        let missed = Event {
+            time: 1,
+            info: Info::ProductionMissed {
+                participant_id: bp,
+                num_assigned: 10,
+                num_missed: 3,
+            },
+        };
+        assert!(stats.push(missed).is_err());
+    }
+
+    #[test]
+    fn test_stake_by_strategy() {
+        let mut stats = StatsAccumulator::default();
+        let (bp, delegator) = (Id::explicit(0), Id::explicit(1));
+        let infos = vec![
+            Info::ParticipantCreated {
+                participant_id: bp,
+                num_tokens: 10.0,
+                strategy: StrategyKind::Greedy,
+            },
+            Info::ParticipantCreated {
+                participant_id: delegator,
+                num_tokens: 5.0,
+                strategy: StrategyKind::LoyalDelegator,
+            },
+            Info::RoleChange {
+                participant_id: bp,
+                new_role: Some(Role::BlockProducer),
+            },
+            Info::RoleChange {
+                participant_id: delegator,
+                new_role: Some(Role::Delegator(bp)),
+            },
+            Info::LiquidChange {
+                participant_id: delegator,
+                change_amount: 15.0,
+            },
+            Info::ParticipantCreated {
+                participant_id: Id::explicit(2),
+                num_tokens: 30.0,
+                strategy: StrategyKind::default(),
+            },
+            Info::ParticipantBankrupt {
+                participant_id: Id::explicit(2),
+            },
+        ];
+        for info in infos {
+            stats.push(Event { time: 0, info }).unwrap();
         }
+        let series = stats.sampled_series(1, 1);
+        let value = |column: &str| {
+            let i = Stats::columns().iter().position(|c| c == column).unwrap();
+            series[0].values()[i]
+        };
+        assert_eq!(value("total_bp_stake"), 15.0);
+        assert_eq!(value("greedy_bp_stake"), 10.0);
+        assert_eq!(value("loyal_delegator_bp_stake"), 5.0);
+        assert_eq!(value("stochastic_bp_stake"), 0.0);
+        // the liquid tokens are not staked, and the tokens of bankrupt participants are gone
+        assert_eq!(value("staking_ratio"), 0.5);
     }
 }
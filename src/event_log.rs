@@ -0,0 +1,145 @@
+use crate::error::SimError;
+use crate::event::{Event, EventConsumer};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    // One JSON object per line.
+    JsonLines,
+    // Events encoded back to back with bincode.
+    Binary,
+}
+
+impl LogFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::JsonLines => "jsonl",
+            Self::Binary => "bin",
+        }
+    }
+
+    // Format of the log at `path`, going by its extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("bin") => Self::Binary,
+            _ => Self::JsonLines,
+        }
+    }
+}
+
+// Streams every event it receives to `writer`, so a run can be post-processed
+// without simulating it again.
+pub struct EventLog<W: Write> {
+    writer: W,
+    format: LogFormat,
+}
+
+impl EventLog<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(file_name: P, format: LogFormat) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(file_name)?), format))
+    }
+}
+
+impl<W: Write> EventLog<W> {
+    pub fn new(writer: W, format: LogFormat) -> Self {
+        Self { writer, format }
+    }
+
+    // Flushes the events which are still buffered. Errors writing them are only
+    // reported here, so this must be called once the run is over.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write(&mut self, event: &Event) -> io::Result<()> {
+        match self.format {
+            LogFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, event)?;
+                self.writer.write_all(b"\n")
+            }
+            LogFormat::Binary => bincode::serialize_into(&mut self.writer, event)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+        }
+    }
+}
+
+impl<W: Write> EventConsumer for EventLog<W> {
+    fn push(&mut self, event: Event) -> Result<(), SimError> {
+        Ok(self.write(&event)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventLog, LogFormat};
+    use crate::event::{Event, EventConsumer, Info};
+    use crate::id::Id;
+    use crate::role::Role;
+    use crate::strategy::StrategyKind;
+
+    fn events() -> Vec<Event> {
+        vec![
+            Event {
+                time: 0,
+                info: Info::ParticipantCreated {
+                    participant_id: Id::explicit(0),
+                    num_tokens: 10.5,
+                    strategy: StrategyKind::default(),
+                },
+            },
+            Event {
+                time: 0,
+                info: Info::RoleChange {
+                    participant_id: Id::explicit(1),
+                    new_role: Some(Role::Delegator(Id::explicit(0))),
+                },
+            },
+            Event {
+                time: 3,
+                info: Info::ParticipantSplit {
+                    participant_id: Id::explicit(0),
+                    new_participant_ids: (Id::explicit(2), Id::explicit(3)),
+                },
+            },
+        ]
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let mut log = EventLog::new(Vec::new(), LogFormat::JsonLines);
+        for e in events() {
+            log.push(e).unwrap();
+        }
+        let bytes = log.finish().unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[1],
+            r#"{"time":0,"info":{"role_change":{"participant_id":1,"new_role":{"delegator":0}}}}"#
+        );
+        let decoded: Vec<Event> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(decoded, events());
+    }
+
+    #[test]
+    fn test_binary() {
+        let mut log = EventLog::new(Vec::new(), LogFormat::Binary);
+        for e in events() {
+            log.push(e).unwrap();
+        }
+        let bytes = log.finish().unwrap();
+        let mut reader = bytes.as_slice();
+        let decoded: Vec<Event> = (0..3)
+            .map(|_| bincode::deserialize_from(&mut reader).unwrap())
+            .collect();
+        assert_eq!(decoded, events());
+        assert!(reader.is_empty());
+    }
+}
@@ -1,32 +1,444 @@
 extern crate rand;
 
+mod error;
 mod event;
+mod event_log;
 mod id;
+mod params;
+mod population;
+mod replay;
 mod role;
+mod runner;
+mod schedule;
+mod security;
 mod sim;
+mod snapshot;
+mod strategy;
+mod supply;
+mod sweep;
 
+use crate::event::StatsAccumulator;
+use crate::event_log::{EventLog, LogFormat};
+use crate::params::Params;
+use crate::security::SecurityMetrics;
 use crate::sim::Simulation;
-use std::path::Path;
-
-fn run_with_params<S: AsRef<Path>, T: AsRef<Path>>(params_path: S, output_path: T) {
-    let params_str = std::fs::read_to_string(params_path).unwrap();
-    let params = serde_json::from_str(&params_str).unwrap();
-    println!("{}", serde_json::to_string(&params).unwrap());
-    let initial_stakes: Vec<f64> = (0..100)
-        .flat_map(|i| {
-            let x = 5000.0 - 2.0 * (i as f64);
-            std::iter::repeat(x).take(i + 1)
-        })
-        .collect();
-
-    let mut simulation = Simulation::new(&initial_stakes, params);
-    let mut events = event::StatsAccumulator::default();
-    simulation.run(40_000, &mut events);
-    events.write_stats(output_path).unwrap();
-    println!("{:?}", simulation.stake_fraction());
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_DURATION: &str = "40000";
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Csv,
+    Json,
+}
+
+impl OutputFormat {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.value_of("format") {
+            Some("json") => Self::Json,
+            _ => Self::Csv,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+}
+
+fn cli() -> App<'static, 'static> {
+    let duration = Arg::with_name("duration")
+        .long("duration")
+        .short("d")
+        .takes_value(true)
+        .help("Number of time steps to simulate");
+    let seed = Arg::with_name("seed")
+        .long("seed")
+        .takes_value(true)
+        .help("Seed for the random number generator; overrides the seed in the params file");
+    let format = Arg::with_name("format")
+        .long("format")
+        .takes_value(true)
+        .possible_values(&["csv", "json"])
+        .default_value("csv")
+        .help("Format of the output files");
+    let output_dir = Arg::with_name("output-dir")
+        .long("output-dir")
+        .short("o")
+        .takes_value(true)
+        .default_value(".")
+        .help("Directory the output files are written to");
+    let threads = Arg::with_name("threads")
+        .long("threads")
+        .short("j")
+        .takes_value(true)
+        .help("Number of worker threads [default: number of CPUs]");
+    let consumers = Arg::with_name("consumers")
+        .long("consumers")
+        .takes_value(true)
+        .use_delimiter(true)
+        .possible_values(&["stats", "security", "events", "none"])
+        .default_value("stats")
+        .help(
+            "Comma separated list of event consumers to attach; \
+             `security` writes the stake needed to take over each shard and the BP set, \
+             `events` writes every event to a log file",
+        );
+    let event_log_format = Arg::with_name("event-log-format")
+        .long("event-log-format")
+        .takes_value(true)
+        .possible_values(&["json-lines", "binary"])
+        .default_value("json-lines")
+        .help("Format of the event log");
+    let checkpoint = Arg::with_name("checkpoint")
+        .long("checkpoint")
+        .takes_value(true)
+        .value_name("FILE")
+        .help(
+            "Write the state at the end of the run to FILE, so it can be continued with `resume`",
+        );
+    let params = Arg::with_name("PARAMS")
+        .required(true)
+        .help("JSON file with the simulation params");
+
+    App::new("near-bp-sim")
+        .about("Simulator for NEAR block producer and chunk-only producer selection")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Run a single simulation")
+                .arg(params.clone())
+                .arg(duration.clone().default_value(DEFAULT_DURATION))
+                .arg(seed.clone())
+                .arg(format.clone())
+                .arg(output_dir.clone())
+                .arg(consumers.clone())
+                .arg(event_log_format.clone())
+                .arg(checkpoint.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("resume")
+                .about("Continue a run from a checkpoint")
+                .arg(
+                    Arg::with_name("CHECKPOINT")
+                        .required(true)
+                        .help("Checkpoint written with `--checkpoint`"),
+                )
+                .arg(
+                    Arg::with_name("PARAMS")
+                        .long("params")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("JSON file with params replacing the ones in the checkpoint"),
+                )
+                .arg(
+                    duration
+                        .clone()
+                        .default_value(DEFAULT_DURATION)
+                        .help("Time step to simulate up to, counted from the start of the run"),
+                )
+                .arg(format.clone())
+                .arg(output_dir.clone())
+                .arg(consumers.clone())
+                .arg(event_log_format.clone())
+                .arg(checkpoint),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Feed the events of a recorded event log into event consumers")
+                .arg(
+                    Arg::with_name("LOG")
+                        .required(true)
+                        .help("Event log; `.bin` files are read as binary, others as JSON Lines"),
+                )
+                .arg(
+                    Arg::with_name("num-shards")
+                        .long("num-shards")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("Number of shards of the recorded run, for the security metrics"),
+                )
+                .arg(format.clone())
+                .arg(output_dir.clone())
+                .arg(consumers)
+                .arg(event_log_format),
+        )
+        .subcommand(
+            SubCommand::with_name("sweep")
+                .about("Run every combination of params in a grid")
+                .arg(
+                    Arg::with_name("SWEEP")
+                        .required(true)
+                        .help("JSON file with the sweep specification"),
+                )
+                .arg(
+                    duration
+                        .clone()
+                        .help("Overrides the duration in the sweep file"),
+                )
+                .arg(threads.clone())
+                .arg(format.clone())
+                .arg(output_dir.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("monte-carlo")
+                .about("Run independent replicates of a simulation and aggregate their stats")
+                .arg(params.clone())
+                .arg(
+                    Arg::with_name("replicates")
+                        .long("replicates")
+                        .short("n")
+                        .takes_value(true)
+                        .required(true)
+                        .validator(|value| match value.parse::<usize>() {
+                            Ok(n) if n > 0 => Ok(()),
+                            _ => Err("must be a positive integer".to_string()),
+                        })
+                        .help("Number of replicates"),
+                )
+                .arg(
+                    Arg::with_name("sample-interval")
+                        .long("sample-interval")
+                        .takes_value(true)
+                        .default_value("100")
+                        .help("Only every n-th time step is aggregated"),
+                )
+                .arg(duration.default_value(DEFAULT_DURATION))
+                .arg(seed)
+                .arg(threads)
+                .arg(format)
+                .arg(output_dir),
+        )
+        .subcommand(
+            SubCommand::with_name("validate-params")
+                .about("Check a params file without running a simulation")
+                .arg(params),
+        )
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    run_with_params(&args[1], &args[2])
+    let matches = cli().get_matches();
+    let result = match matches.subcommand() {
+        ("run", Some(m)) => run(m),
+        ("sweep", Some(m)) => sweep(m),
+        ("resume", Some(m)) => resume(m),
+        ("replay", Some(m)) => replay(m),
+        ("monte-carlo", Some(m)) => monte_carlo(m),
+        ("validate-params", Some(m)) => validate_params(m),
+        _ => unreachable!("clap requires a subcommand"),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> io::Result<()> {
+    let params = read_params(matches)?;
+    let duration = parse_arg(matches, "duration")?.unwrap();
+    let format = OutputFormat::from_matches(matches);
+    let output_dir = output_dir(matches)?;
+    println!("{}", serde_json::to_string(&params)?);
+
+    let num_shards = params.num_shards;
+    let mut simulation = Simulation::new(params)?;
+    let mut consumers = consumers(matches, &output_dir, num_shards)?;
+    simulation.run(duration, &mut consumers)?;
+    finish_consumers(consumers, format, &output_dir)?;
+    if let Some(path) = matches.value_of("checkpoint") {
+        simulation.save(path)?;
+    }
+    println!("{:?}", simulation.stake_fraction());
+    Ok(())
+}
+
+fn resume(matches: &ArgMatches) -> io::Result<()> {
+    let path = matches.value_of("CHECKPOINT").unwrap();
+    let mut simulation =
+        Simulation::load(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    if matches.is_present("PARAMS") {
+        simulation
+            .set_params(read_params(matches)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    let duration = parse_arg(matches, "duration")?.unwrap();
+    let format = OutputFormat::from_matches(matches);
+    let output_dir = output_dir(matches)?;
+    println!("resuming at time {}", simulation.time());
+
+    let mut consumers = consumers(matches, &output_dir, simulation.params().num_shards)?;
+    simulation.run(duration, &mut consumers)?;
+    finish_consumers(consumers, format, &output_dir)?;
+    if let Some(path) = matches.value_of("checkpoint") {
+        simulation.save(path)?;
+    }
+    println!("{:?}", simulation.stake_fraction());
+    Ok(())
+}
+
+fn replay(matches: &ArgMatches) -> io::Result<()> {
+    let path = matches.value_of("LOG").unwrap();
+    let reader = replay::EventReader::open(path, LogFormat::from_path(path))
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    let format = OutputFormat::from_matches(matches);
+    let output_dir = output_dir(matches)?;
+    let num_shards = parse_arg(matches, "num-shards")?.unwrap();
+
+    let mut consumers = consumers(matches, &output_dir, num_shards)?;
+    let count = replay::replay(reader, &mut consumers)?;
+    finish_consumers(consumers, format, &output_dir)?;
+    println!("replayed {} events", count);
+    Ok(())
+}
+
+fn sweep(matches: &ArgMatches) -> io::Result<()> {
+    let sweep_str = read_file(matches.value_of("SWEEP").unwrap())?;
+    let mut sweep: sweep::SweepSpec = serde_json::from_str(&sweep_str)?;
+    if let Some(duration) = parse_arg(matches, "duration")? {
+        sweep.duration = duration;
+    }
+    let format = OutputFormat::from_matches(matches);
+    let output_dir = output_dir(matches)?;
+
+    let rows = sweep.run(num_threads(matches)?)?;
+    let path = output_dir.join(format!("sweep.{}", format.extension()));
+    match format {
+        OutputFormat::Csv => sweep.write_rows(&rows, path),
+        OutputFormat::Json => sweep.write_rows_json(&rows, path),
+    }
+}
+
+fn monte_carlo(matches: &ArgMatches) -> io::Result<()> {
+    let monte_carlo = runner::MonteCarlo {
+        params: read_params(matches)?,
+        replicates: parse_arg(matches, "replicates")?.unwrap(),
+        duration: parse_arg(matches, "duration")?.unwrap(),
+        sample_interval: parse_arg(matches, "sample-interval")?.unwrap(),
+        percentiles: vec![5.0, 25.0, 50.0, 75.0, 95.0],
+    };
+    let format = OutputFormat::from_matches(matches);
+    let output_dir = output_dir(matches)?;
+
+    let aggregate = monte_carlo.run(num_threads(matches)?)?;
+    let path = output_dir.join(format!("monte_carlo.{}", format.extension()));
+    match format {
+        OutputFormat::Csv => aggregate.write_stats(path)?,
+        OutputFormat::Json => aggregate.write_stats_json(path)?,
+    }
+    println!("{:?}", aggregate.seeds);
+    Ok(())
+}
+
+fn validate_params(matches: &ArgMatches) -> io::Result<()> {
+    // `read_params` reports every invalid field
+    let params = read_params(matches)?;
+    println!("{}", serde_json::to_string_pretty(&params)?);
+    println!("params are valid");
+    Ok(())
+}
+
+type Consumers = (
+    Option<StatsAccumulator>,
+    Option<SecurityMetrics>,
+    Option<EventLog<BufWriter<File>>>,
+);
+
+// The event consumers selected with `--consumers`, for a run with `num_shards` shards.
+fn consumers(matches: &ArgMatches, output_dir: &Path, num_shards: usize) -> io::Result<Consumers> {
+    let names: Vec<&str> = matches.values_of("consumers").unwrap().collect();
+    let stats = if names.contains(&"stats") {
+        Some(StatsAccumulator::default())
+    } else {
+        None
+    };
+    let security = if names.contains(&"security") {
+        Some(SecurityMetrics::new(num_shards))
+    } else {
+        None
+    };
+    let event_log = if names.contains(&"events") {
+        let log_format = match matches.value_of("event-log-format") {
+            Some("binary") => LogFormat::Binary,
+            _ => LogFormat::JsonLines,
+        };
+        let path = output_dir.join(format!("events.{}", log_format.extension()));
+        Some(EventLog::create(path, log_format)?)
+    } else {
+        None
+    };
+    Ok((stats, security, event_log))
+}
+
+// Writes the output of the consumers once all events have been pushed.
+fn finish_consumers(
+    consumers: Consumers,
+    format: OutputFormat,
+    output_dir: &Path,
+) -> io::Result<()> {
+    let (stats, security, event_log) = consumers;
+    if let Some(event_log) = event_log {
+        event_log.finish()?;
+    }
+    if let Some(mut stats) = stats {
+        let path = output_dir.join(format!("stats.{}", format.extension()));
+        match format {
+            OutputFormat::Csv => stats.write_stats(path)?,
+            OutputFormat::Json => stats.write_stats_json(path)?,
+        }
+    }
+    if let Some(mut security) = security {
+        let path = output_dir.join(format!("security.{}", format.extension()));
+        match format {
+            OutputFormat::Csv => security.write_metrics(path)?,
+            OutputFormat::Json => security.write_metrics_json(path)?,
+        }
+    }
+    Ok(())
+}
+
+// Params from the `PARAMS` file, with the seed replaced by `--seed` if given.
+fn read_params(matches: &ArgMatches) -> io::Result<Params> {
+    let path = matches.value_of("PARAMS").unwrap();
+    let params_str = read_file(path)?;
+    let mut params: Params = serde_json::from_str(&params_str)?;
+    if let Some(seed) = parse_arg(matches, "seed")? {
+        params.seed = Some(seed);
+    }
+    params
+        .validate()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    Ok(params)
+}
+
+fn read_file(path: &str) -> io::Result<String> {
+    std::fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+}
+
+fn output_dir(matches: &ArgMatches) -> io::Result<PathBuf> {
+    let dir = Path::new(matches.value_of("output-dir").unwrap());
+    std::fs::create_dir_all(dir)?;
+    Ok(dir.to_path_buf())
+}
+
+fn num_threads(matches: &ArgMatches) -> io::Result<usize> {
+    Ok(parse_arg(matches, "threads")?.unwrap_or_else(num_cpus::get))
+}
+
+fn parse_arg<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> io::Result<Option<T>> {
+    match matches.value_of(name) {
+        None => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid value for --{}: {}", name, value),
+            )
+        }),
+    }
 }
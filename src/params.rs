@@ -0,0 +1,588 @@
+use crate::id::Id;
+use crate::population::InitialStakes;
+use crate::schedule::Schedule;
+use crate::strategy::{self, StrategyKind, StrategyShare};
+use crate::supply::RewardModel;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+// Fields omitted from a params file take their value from `Params::default()`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Params {
+    pub num_block_producers: usize,
+    pub num_chunk_only_producers: usize,
+    pub chunk_only_producer_cost: f64,
+    pub block_producer_cost_factor: f64,
+    pub total_reward: f64,
+    // Whether the rewards are the fixed `total_reward` or come from inflating the supply.
+    pub reward_model: RewardModel,
+    pub block_producer_reward_fraction: f64,
+    pub block_producer_delegation_fee: f64,
+    pub chunk_only_producer_delegation_fee: f64,
+    // Seed for the random number generator driving the simulation. Runs with the
+    // same seed and params produce the same event stream. If omitted a random
+    // seed is used.
+    pub seed: Option<u64>,
+    // Stakes of the participants present at the start of the simulation.
+    pub initial_stakes: InitialStakes,
+    // What happens to delegators whose delegatee is merged, split, goes bankrupt, is
+    // kicked out, or is not selected as a producer.
+    pub dangling_delegation_policy: DanglingDelegationPolicy,
+    // How BPs and COPs are chosen among the proposals.
+    pub selection_mode: SelectionMode,
+    // Number of time steps in an epoch. Rewards are paid and roles are selected at the
+    // end of every epoch; `total_reward` and the costs are still per time step.
+    pub epoch_length: usize,
+    // Roles proposed in epoch T take effect in epoch T + `activation_delay`. NEAR uses 2.
+    pub activation_delay: usize,
+    // Every participant produces each block (chunk) assigned to it with a fixed
+    // probability, drawn uniformly from this range when the participant is created.
+    pub min_reliability: f64,
+    pub max_reliability: f64,
+    // Producers which produce less than this fraction of their assigned blocks (chunks)
+    // in an epoch are kicked out: they get no rewards and lose their seat for the next
+    // epoch. Producers above it get rewards in proportion to what they produced.
+    pub online_threshold: f64,
+    // Probability that a BP (COP) misbehaves, e.g. by signing two blocks at the same
+    // height, in an epoch.
+    pub block_producer_misbehaviour_probability: f64,
+    pub chunk_only_producer_misbehaviour_probability: f64,
+    // Misbehaviour which happens regardless of the probabilities above.
+    pub scripted_misbehaviour: Vec<Misbehaviour>,
+    // Fraction of the tokens of a misbehaving producer, and of each of its delegators,
+    // which is burned. Tokens which are being unstaked are exempt, since it is not
+    // tracked which producer they were staked with.
+    pub slash_fraction: f64,
+    // How the participants choose their roles. Participants not covered by
+    // `strategy_mix` follow `strategy`.
+    pub strategy: StrategyKind,
+    pub strategy_mix: Vec<StrategyShare>,
+    // How fast participants with the `bandit` strategy update their value estimates, and
+    // the probability that they try a random role instead of the best one.
+    pub bandit_learning_rate: f64,
+    pub bandit_exploration: f64,
+    // COPs are assigned to shards so that the stake of the shards is balanced. The COP
+    // rewards are split between the shards in proportion to `shard_weights` (equally if
+    // empty), and the cost of producing chunks of a shard scales with its weight.
+    pub num_shards: usize,
+    pub shard_weights: Vec<f64>,
+    // Return per token and time step of holding tokens outside staking (DeFi, lending).
+    // Only liquid balances earn it; the tokens of participants without a role earn nothing
+    // until they are moved there (see `rebalance_rate`).
+    pub external_yield: f64,
+    // Fraction of the tokens of a new participant which it keeps liquid.
+    pub liquid_fraction: f64,
+    // At the end of every epoch participants move this fraction of their stake to their
+    // liquid balance if staking paid less per token than `external_yield` (participants
+    // without a role were paid nothing), and this fraction of their liquid balance to
+    // their stake otherwise.
+    pub rebalance_rate: f64,
+    // Number of epochs tokens are locked after they are unstaked, earning nothing. Stake is
+    // unstaked when a participant chooses to delegate to another producer or to stay out
+    // (it has no role until the tokens are withdrawn), and when it is moved to the liquid
+    // balance. Delegations assigned by the selection move the stake without unstaking, and
+    // the tokens of participants without a role are not staked, so they are never locked.
+    pub unstake_delay: usize,
+    // Fields which change during the run, e.g. `total_reward` decreasing every epoch.
+    // Nested fields are addressed with dots, like in a sweep. The schedules are applied
+    // at the end of every epoch and at their breakpoints (e.g. the start of a ramp); in
+    // between the fields keep their value.
+    pub schedules: BTreeMap<String, Schedule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DanglingDelegationPolicy {
+    // The delegators lose their role until the next role assignment.
+    Undelegate,
+    // The delegators follow the participant their delegatee was merged into; if it was
+    // split they alternate between the two halves. Delegators of a bankrupt delegatee
+    // are undelegated.
+    FollowSuccessor,
+    // The delegators move to the producer paying delegators the most per token.
+    BestProducer,
+}
+
+impl Default for DanglingDelegationPolicy {
+    fn default() -> Self {
+        Self::Undelegate
+    }
+}
+
+// The participant misbehaves in the epoch containing `time`. Nothing happens if it is not
+// a producer at the end of that epoch.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Misbehaviour {
+    pub time: usize,
+    pub participant_id: Id,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            num_block_producers: 100,
+            num_chunk_only_producers: 300,
+            chunk_only_producer_cost: 5.0,
+            block_producer_cost_factor: 7.0,
+            total_reward: 3000.0,
+            reward_model: RewardModel::default(),
+            block_producer_reward_fraction: 0.6,
+            block_producer_delegation_fee: 0.15,
+            chunk_only_producer_delegation_fee: 0.05,
+            seed: None,
+            initial_stakes: InitialStakes::default(),
+            dangling_delegation_policy: DanglingDelegationPolicy::default(),
+            selection_mode: SelectionMode::default(),
+            epoch_length: 1,
+            activation_delay: 1,
+            min_reliability: 1.0,
+            max_reliability: 1.0,
+            online_threshold: 0.9,
+            block_producer_misbehaviour_probability: 0.0,
+            chunk_only_producer_misbehaviour_probability: 0.0,
+            scripted_misbehaviour: Vec::new(),
+            slash_fraction: 0.1,
+            strategy: StrategyKind::default(),
+            strategy_mix: Vec::new(),
+            bandit_learning_rate: 0.1,
+            bandit_exploration: 0.1,
+            num_shards: 1,
+            shard_weights: Vec::new(),
+            external_yield: 0.0,
+            liquid_fraction: 0.0,
+            rebalance_rate: 0.0,
+            unstake_delay: 0,
+            schedules: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionMode {
+    // The `num_block_producers` (`num_chunk_only_producers`) largest proposals are
+    // accepted; all other proposals delegate to one of them.
+    TopN,
+    // Like NEAR: the seat price is the largest price at which the proposals buy
+    // `num_block_producers` (`num_chunk_only_producers`) seats, where each proposal
+    // buys `stake / price` seats rounded down. Proposals below the seat price are
+    // rejected and the participant is left without a role.
+    SeatPrice,
+}
+
+impl Default for SelectionMode {
+    fn default() -> Self {
+        Self::TopN
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidParam {
+    pub field: String,
+    pub message: String,
+}
+
+// All problems found in a set of params.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamsError {
+    pub problems: Vec<InvalidParam>,
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid params:")?;
+        for p in self.problems.iter() {
+            write!(f, "\n  {}: {}", p.field, p.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+// Collects the problems found while validating params.
+#[derive(Default)]
+pub struct Validator {
+    problems: Vec<InvalidParam>,
+}
+
+impl Validator {
+    pub fn check(&mut self, condition: bool, field: &str, message: &str) {
+        if !condition {
+            self.problems.push(InvalidParam {
+                field: field.to_string(),
+                message: message.to_string(),
+            });
+        }
+    }
+
+    pub fn non_negative(&mut self, value: f64, field: &str) {
+        self.check(
+            value.is_finite() && value >= 0.0,
+            field,
+            &format!("must be a non-negative number, found {}", value),
+        );
+    }
+
+    pub fn positive(&mut self, value: f64, field: &str) {
+        self.check(
+            value.is_finite() && value > 0.0,
+            field,
+            &format!("must be a positive number, found {}", value),
+        );
+    }
+
+    pub fn fraction(&mut self, value: f64, field: &str) {
+        self.check(
+            (0.0..=1.0).contains(&value),
+            field,
+            &format!("must be between 0 and 1, found {}", value),
+        );
+    }
+
+    pub fn finish(self) -> Result<(), ParamsError> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ParamsError {
+                problems: self.problems,
+            })
+        }
+    }
+}
+
+impl Params {
+    // Reliability of a newly created participant. Does not use `rng` if the range is
+    // a single value.
+    pub fn draw_reliability<R: Rng>(&self, rng: &mut R) -> f64 {
+        if self.min_reliability < self.max_reliability {
+            rng.gen_range(self.min_reliability..=self.max_reliability)
+        } else {
+            self.max_reliability
+        }
+    }
+
+    // Strategy of a newly created participant. Does not use `rng` if there is no mix.
+    pub fn draw_strategy<R: Rng>(&self, rng: &mut R) -> StrategyKind {
+        strategy::draw_strategy(&self.strategy_mix, self.strategy, rng)
+    }
+
+    // Weight of every shard, scaled so that the average weight is 1.
+    pub fn shard_weights(&self) -> Vec<f64> {
+        if self.shard_weights.len() != self.num_shards {
+            return vec![1.0; self.num_shards];
+        }
+        let total: f64 = self.shard_weights.iter().sum();
+        self.shard_weights
+            .iter()
+            .map(|w| w * (self.num_shards as f64) / total)
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<(), ParamsError> {
+        let mut v = Validator::default();
+        v.check(
+            self.num_block_producers > 0,
+            "num_block_producers",
+            "must be at least 1",
+        );
+        v.check(
+            self.num_chunk_only_producers > 0,
+            "num_chunk_only_producers",
+            "must be at least 1",
+        );
+        v.check(self.epoch_length > 0, "epoch_length", "must be at least 1");
+        v.check(
+            self.activation_delay > 0,
+            "activation_delay",
+            "must be at least 1",
+        );
+        v.fraction(self.min_reliability, "min_reliability");
+        v.fraction(self.max_reliability, "max_reliability");
+        v.check(
+            self.min_reliability <= self.max_reliability,
+            "max_reliability",
+            "must not be less than min_reliability",
+        );
+        v.fraction(self.online_threshold, "online_threshold");
+        v.fraction(
+            self.block_producer_misbehaviour_probability,
+            "block_producer_misbehaviour_probability",
+        );
+        v.fraction(
+            self.chunk_only_producer_misbehaviour_probability,
+            "chunk_only_producer_misbehaviour_probability",
+        );
+        v.fraction(self.slash_fraction, "slash_fraction");
+        v.check(self.num_shards > 0, "num_shards", "must be at least 1");
+        v.check(
+            self.shard_weights.is_empty() || self.shard_weights.len() == self.num_shards,
+            "shard_weights",
+            &format!(
+                "must be empty or have num_shards entries, found {}",
+                self.shard_weights.len()
+            ),
+        );
+        for (i, weight) in self.shard_weights.iter().enumerate() {
+            v.positive(*weight, &format!("shard_weights.{}", i));
+        }
+        v.non_negative(self.external_yield, "external_yield");
+        v.fraction(self.liquid_fraction, "liquid_fraction");
+        v.fraction(self.rebalance_rate, "rebalance_rate");
+        for (i, share) in self.strategy_mix.iter().enumerate() {
+            v.fraction(
+                share.stake_share,
+                &format!("strategy_mix.{}.stake_share", i),
+            );
+        }
+        v.fraction(self.bandit_learning_rate, "bandit_learning_rate");
+        v.fraction(self.bandit_exploration, "bandit_exploration");
+        let total_share: f64 = self.strategy_mix.iter().map(|s| s.stake_share).sum();
+        v.check(
+            total_share <= 1.0 + 1e-9,
+            "strategy_mix",
+            &format!(
+                "stake shares must not add up to more than 1, found {}",
+                total_share
+            ),
+        );
+        v.non_negative(self.chunk_only_producer_cost, "chunk_only_producer_cost");
+        v.non_negative(
+            self.block_producer_cost_factor,
+            "block_producer_cost_factor",
+        );
+        v.non_negative(self.total_reward, "total_reward");
+        self.reward_model.validate(&mut v);
+        v.fraction(
+            self.block_producer_reward_fraction,
+            "block_producer_reward_fraction",
+        );
+        v.fraction(
+            self.block_producer_delegation_fee,
+            "block_producer_delegation_fee",
+        );
+        v.fraction(
+            self.chunk_only_producer_delegation_fee,
+            "chunk_only_producer_delegation_fee",
+        );
+        self.initial_stakes.validate(&mut v);
+        for (field, schedule) in self.schedules.iter() {
+            let name = format!("schedules.{}", field);
+            v.check(
+                !["seed", "initial_stakes", "schedules"]
+                    .iter()
+                    .any(|f| field == f || field.starts_with(&format!("{}.", f))),
+                &name,
+                "cannot change during a run",
+            );
+            schedule.validate(&name, &mut v);
+        }
+        if !v.problems.is_empty() {
+            return v.finish();
+        }
+
+        // the scheduled values must be valid whenever they change, and at the end of an
+        // arbitrarily long run, which is what epoch multipliers approach
+        let mut times: Vec<usize> = self
+            .schedules
+            .values()
+            .flat_map(|s| s.breakpoints())
+            .collect();
+        times.push(0);
+        times.push(usize::MAX);
+        times.sort_unstable();
+        times.dedup();
+        for time in times {
+            if let Err(e) = self.at(time) {
+                for p in e.problems {
+                    let message = if time == usize::MAX {
+                        // the value found there is only a cap, so it means nothing to users
+                        let rule = p.message.split(", found ").next().unwrap_or(&p.message);
+                        format!("{} in the long run", rule)
+                    } else {
+                        format!("{} at time {}", p.message, time)
+                    };
+                    v.check(false, &p.field, &message);
+                }
+                break;
+            }
+        }
+        v.finish()
+    }
+
+    // Params in effect at `time`, with all schedules applied. The result has no
+    // schedules and default `initial_stakes`, since those only matter at the start.
+    pub fn at(&self, time: usize) -> Result<Cow<'_, Params>, ParamsError> {
+        if self.schedules.is_empty() {
+            return Ok(Cow::Borrowed(self));
+        }
+        let invalid = |e: serde_json::Error| ParamsError {
+            problems: vec![InvalidParam {
+                field: "schedules".to_string(),
+                message: e.to_string(),
+            }],
+        };
+        let mut v = Validator::default();
+        let base = Params {
+            initial_stakes: InitialStakes::default(),
+            schedules: BTreeMap::new(),
+            ..self.clone()
+        };
+        let mut value = serde_json::to_value(&base).map_err(invalid)?;
+        for (field, schedule) in self.schedules.iter() {
+            let name = format!("schedules.{}", field);
+            match field_mut(&mut value, field) {
+                None => v.check(false, &name, "unknown params field"),
+                Some(target) => match schedule.value_at(target, time) {
+                    Some(new_value) => *target = new_value,
+                    None => v.check(false, &name, "field is not a number"),
+                },
+            }
+        }
+        v.finish()?;
+        let params: Params = serde_json::from_value(value).map_err(invalid)?;
+        params.validate()?;
+        Ok(Cow::Owned(params))
+    }
+}
+
+// The (possibly nested) field `path` of the JSON representation of some params.
+fn field_mut<'a>(value: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    let mut target = value;
+    for key in path.split('.') {
+        target = target.as_object_mut()?.get_mut(key)?;
+    }
+    Some(target)
+}
+
+// Replace the (possibly nested) field `path` of `value`. The field must already exist
+// so that typos are reported instead of silently ignored.
+pub fn set_field(value: &mut Value, path: &str, new_value: Value) -> io::Result<()> {
+    let target = field_mut(value, path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown params field: {}", path),
+        )
+    })?;
+    *target = new_value;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_field, Params};
+    use serde_json::json;
+
+    #[test]
+    fn test_omitted_fields_use_defaults() {
+        let params: Params =
+            serde_json::from_str(r#"{"num_block_producers": 7, "total_reward": 10.5}"#).unwrap();
+        assert_eq!(params.num_block_producers, 7);
+        assert_eq!(params.total_reward, 10.5);
+        assert_eq!(
+            params.num_chunk_only_producers,
+            Params::default().num_chunk_only_producers
+        );
+        assert!(params.validate().is_ok());
+
+        assert!(serde_json::from_str::<Params>(r#"{"num_block_producer": 7}"#).is_err());
+    }
+
+    #[test]
+    fn test_all_problems_are_reported() {
+        let params: Params = serde_json::from_str(
+            r#"{
+                "num_block_producers": 0,
+                "total_reward": -1.0,
+                "block_producer_delegation_fee": 1.5,
+                "initial_stakes": {"distribution": "uniform", "num_participants": 10, "min_stake": 5.0, "max_stake": 1.0}
+            }"#,
+        )
+        .unwrap();
+        let error = params.validate().unwrap_err();
+        let fields: Vec<&str> = error.problems.iter().map(|p| p.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "num_block_producers",
+                "total_reward",
+                "block_producer_delegation_fee",
+                "initial_stakes.max_stake",
+            ]
+        );
+        assert!(error
+            .to_string()
+            .contains("total_reward: must be a non-negative number, found -1"));
+    }
+
+    #[test]
+    fn test_schedules() {
+        let params: Params = serde_json::from_value(json!({
+            "num_block_producers": 100,
+            "block_producer_delegation_fee": 0.5,
+            "schedules": {
+                "num_block_producers": {"kind": "steps", "changes": [{"time": 10, "value": 120}]},
+                "block_producer_delegation_fee": {
+                    "kind": "ramp", "start_time": 0, "end_time": 100, "from": 0.5, "to": 1.0
+                }
+            }
+        }))
+        .unwrap();
+        assert!(params.validate().is_ok());
+        let at_50 = params.at(50).unwrap();
+        assert_eq!(at_50.num_block_producers, 120);
+        assert_eq!(at_50.block_producer_delegation_fee, 0.75);
+        assert!(at_50.schedules.is_empty());
+        assert_eq!(params.at(5).unwrap().num_block_producers, 100);
+
+        let mut value = serde_json::to_value(&params).unwrap();
+        set_field(
+            &mut value,
+            "schedules.block_producer_delegation_fee.to",
+            json!(1.5),
+        )
+        .unwrap();
+        let params: Params = serde_json::from_value(value).unwrap();
+        let error = params.validate().unwrap_err();
+        assert_eq!(error.problems.len(), 1);
+        assert_eq!(
+            error.problems[0].message,
+            "must be between 0 and 1, found 1.5 at time 100"
+        );
+
+        // a growing fee is valid at first, but not by the end of a long run
+        let params: Params = serde_json::from_value(json!({
+            "block_producer_delegation_fee": 0.5,
+            "schedules": {
+                "block_producer_delegation_fee": {
+                    "kind": "epoch_multiplier", "epoch_length": 10, "multiplier": 1.5
+                }
+            }
+        }))
+        .unwrap();
+        let error = params.validate().unwrap_err();
+        assert_eq!(error.problems[0].field, "block_producer_delegation_fee");
+        assert_eq!(
+            error.problems[0].message,
+            "must be between 0 and 1 in the long run"
+        );
+    }
+
+    #[test]
+    fn test_set_field() {
+        let mut value = json!({"a": 1, "b": {"c": 2}});
+        set_field(&mut value, "b.c", json!(3)).unwrap();
+        set_field(&mut value, "a", json!(4)).unwrap();
+        assert_eq!(value, json!({"a": 4, "b": {"c": 3}}));
+        assert!(set_field(&mut value, "b.d", json!(5)).is_err());
+    }
+}
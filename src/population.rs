@@ -0,0 +1,300 @@
+use crate::params::Validator;
+use crate::sim::InitialParticipant;
+use crate::snapshot;
+
+use rand::Rng;
+use rand_distr::{Distribution, LogNormal, Pareto, Uniform};
+
+use serde::{Deserialize, Serialize};
+
+use std::io;
+use std::path::PathBuf;
+
+// Description of the participants present at the start of the simulation. All
+// variants except `ValidatorSnapshot` only describe stakes; those participants
+// start without a role.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "distribution", rename_all = "snake_case")]
+pub enum InitialStakes {
+    // `num_groups` groups of participants, where group `i` has `i + 1` members,
+    // each with stake `max_stake - step * i`.
+    Triangle {
+        num_groups: usize,
+        max_stake: f64,
+        step: f64,
+    },
+    Uniform {
+        num_participants: usize,
+        min_stake: f64,
+        max_stake: f64,
+    },
+    // Stakes `exp(mu + sigma * Z)` where `Z` is a standard normal variable.
+    LogNormal {
+        num_participants: usize,
+        mu: f64,
+        sigma: f64,
+    },
+    // Power-law distributed stakes of at least `scale`; smaller `shape` means heavier tail.
+    Pareto {
+        num_participants: usize,
+        scale: f64,
+        shape: f64,
+    },
+    // Rank-size law: the participant of rank `k` (starting from 1) has stake
+    // `max_stake / k^exponent`.
+    Zipf {
+        num_participants: usize,
+        max_stake: f64,
+        exponent: f64,
+    },
+    Explicit {
+        stakes: Vec<f64>,
+    },
+    // File with one stake per line. If there are several comma separated columns
+    // the first one is used. Non-numeric lines (e.g. a header) are skipped.
+    Csv {
+        path: PathBuf,
+    },
+    // JSON dump of the `validators` RPC response, see `snapshot.rs`. Validators start
+    // with their current role and their delegators delegate to them.
+    ValidatorSnapshot {
+        path: PathBuf,
+    },
+}
+
+impl Default for InitialStakes {
+    fn default() -> Self {
+        Self::Triangle {
+            num_groups: 100,
+            max_stake: 5000.0,
+            step: 2.0,
+        }
+    }
+}
+
+impl InitialStakes {
+    pub fn validate(&self, v: &mut Validator) {
+        let count = |v: &mut Validator, n: usize| {
+            v.check(
+                n > 0,
+                "initial_stakes.num_participants",
+                "must be at least 1",
+            )
+        };
+        match self {
+            Self::Triangle {
+                num_groups,
+                max_stake,
+                step,
+            } => {
+                v.check(
+                    *num_groups > 0,
+                    "initial_stakes.num_groups",
+                    "must be at least 1",
+                );
+                v.non_negative(*step, "initial_stakes.step");
+                v.check(
+                    max_stake - step * ((num_groups.max(&1) - 1) as f64) > 0.0,
+                    "initial_stakes.max_stake",
+                    "must be large enough for all stakes to be positive",
+                );
+            }
+            Self::Uniform {
+                num_participants,
+                min_stake,
+                max_stake,
+            } => {
+                count(v, *num_participants);
+                v.positive(*min_stake, "initial_stakes.min_stake");
+                v.check(
+                    max_stake > min_stake,
+                    "initial_stakes.max_stake",
+                    "must be greater than min_stake",
+                );
+            }
+            Self::LogNormal {
+                num_participants,
+                mu,
+                sigma,
+            } => {
+                count(v, *num_participants);
+                v.check(
+                    mu.is_finite(),
+                    "initial_stakes.mu",
+                    "must be a finite number",
+                );
+                v.non_negative(*sigma, "initial_stakes.sigma");
+            }
+            Self::Pareto {
+                num_participants,
+                scale,
+                shape,
+            } => {
+                count(v, *num_participants);
+                v.positive(*scale, "initial_stakes.scale");
+                v.positive(*shape, "initial_stakes.shape");
+            }
+            Self::Zipf {
+                num_participants,
+                max_stake,
+                exponent,
+            } => {
+                count(v, *num_participants);
+                v.positive(*max_stake, "initial_stakes.max_stake");
+                v.non_negative(*exponent, "initial_stakes.exponent");
+            }
+            Self::Explicit { stakes } => {
+                v.check(
+                    !stakes.is_empty(),
+                    "initial_stakes.stakes",
+                    "must not be empty",
+                );
+                for s in stakes {
+                    v.positive(*s, "initial_stakes.stakes");
+                }
+            }
+            // checked when the file is loaded
+            Self::Csv { .. } | Self::ValidatorSnapshot { .. } => (),
+        }
+    }
+
+    pub fn generate<R: Rng>(&self, rng: &mut R) -> io::Result<Vec<InitialParticipant>> {
+        if let Self::ValidatorSnapshot { path } = self {
+            return snapshot::load(path);
+        }
+        let participants = self
+            .stakes(rng)?
+            .into_iter()
+            .map(|num_tokens| InitialParticipant {
+                num_tokens,
+                role: None,
+            })
+            .collect();
+        Ok(participants)
+    }
+
+    fn stakes<R: Rng>(&self, rng: &mut R) -> io::Result<Vec<f64>> {
+        let stakes = match self {
+            Self::Triangle {
+                num_groups,
+                max_stake,
+                step,
+            } => (0..*num_groups)
+                .flat_map(|i| {
+                    let x = max_stake - step * (i as f64);
+                    vec![x; i + 1]
+                })
+                .collect(),
+            Self::Uniform {
+                num_participants,
+                min_stake,
+                max_stake,
+            } => sample(Uniform::new(min_stake, max_stake), *num_participants, rng),
+            Self::LogNormal {
+                num_participants,
+                mu,
+                sigma,
+            } => {
+                let distribution = LogNormal::new(*mu, *sigma)
+                    .map_err(|e| invalid_input(&format!("log_normal: {}", e)))?;
+                sample(distribution, *num_participants, rng)
+            }
+            Self::Pareto {
+                num_participants,
+                scale,
+                shape,
+            } => {
+                let distribution = Pareto::new(*scale, *shape)
+                    .map_err(|e| invalid_input(&format!("pareto: {}", e)))?;
+                sample(distribution, *num_participants, rng)
+            }
+            Self::Zipf {
+                num_participants,
+                max_stake,
+                exponent,
+            } => (1..=*num_participants)
+                .map(|k| max_stake / (k as f64).powf(*exponent))
+                .collect(),
+            Self::Explicit { stakes } => stakes.clone(),
+            Self::Csv { path } => parse_csv(&std::fs::read_to_string(path)?)?,
+            // `generate` loads snapshots itself, since they also give the roles
+            Self::ValidatorSnapshot { path } => snapshot::load(path)?
+                .into_iter()
+                .map(|p| p.num_tokens)
+                .collect(),
+        };
+
+        if let Some(s) = stakes.iter().find(|s| !s.is_finite() || **s <= 0.0) {
+            return Err(invalid_input(&format!(
+                "initial stakes must be positive, found {}",
+                s
+            )));
+        }
+        Ok(stakes)
+    }
+}
+
+fn sample<D: Distribution<f64>, R: Rng>(distribution: D, n: usize, rng: &mut R) -> Vec<f64> {
+    distribution.sample_iter(rng).take(n).collect()
+}
+
+fn parse_csv(contents: &str) -> io::Result<Vec<f64>> {
+    let stakes: Vec<f64> = contents
+        .lines()
+        .filter_map(|line| line.split(',').next())
+        .filter_map(|field| field.trim().parse().ok())
+        .collect();
+    if stakes.is_empty() {
+        return Err(invalid_input("no stakes found in CSV file"));
+    }
+    Ok(stakes)
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_csv, InitialStakes};
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_default_is_triangle() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let stakes = InitialStakes::default().stakes(&mut rng).unwrap();
+        assert_eq!(stakes.len(), 5050);
+        assert_eq!(stakes[0], 5000.0);
+        assert_eq!(stakes[1], 4998.0);
+        assert_eq!(stakes[2], 4998.0);
+        assert_eq!(stakes[5049], 4802.0);
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let stakes = parse_csv("stake,name\n100.5,alice\n\n20,bob\n3\n").unwrap();
+        assert_eq!(stakes, vec![100.5, 20.0, 3.0]);
+        assert!(parse_csv("stake\n").is_err());
+    }
+
+    #[test]
+    fn test_distribution_from_json() {
+        let spec: InitialStakes = serde_json::from_str(
+            r#"{"distribution": "pareto", "num_participants": 50, "scale": 10.0, "shape": 1.5}"#,
+        )
+        .unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let participants = spec.generate(&mut rng).unwrap();
+        assert_eq!(participants.len(), 50);
+        assert!(participants
+            .iter()
+            .all(|p| p.num_tokens >= 10.0 && p.role.is_none()));
+
+        let spec = InitialStakes::Zipf {
+            num_participants: 3,
+            max_stake: 900.0,
+            exponent: 2.0,
+        };
+        assert_eq!(spec.stakes(&mut rng).unwrap(), vec![900.0, 225.0, 100.0]);
+    }
+}
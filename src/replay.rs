@@ -0,0 +1,327 @@
+use crate::error::SimError;
+use crate::event::{Event, EventConsumer, Info};
+use crate::event_log::LogFormat;
+use crate::id::Id;
+use crate::role::Role;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+// Reads the events written by an `EventLog`, in the order they were written.
+pub struct EventReader<R: BufRead> {
+    reader: R,
+    format: LogFormat,
+    line: String,
+}
+
+impl EventReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(file_name: P, format: LogFormat) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(file_name)?), format))
+    }
+}
+
+impl<R: BufRead> EventReader<R> {
+    pub fn new(reader: R, format: LogFormat) -> Self {
+        Self {
+            reader,
+            format,
+            line: String::new(),
+        }
+    }
+
+    fn read(&mut self) -> io::Result<Option<Event>> {
+        match self.format {
+            LogFormat::JsonLines => loop {
+                self.line.clear();
+                if self.reader.read_line(&mut self.line)? == 0 {
+                    return Ok(None);
+                }
+                if !self.line.trim().is_empty() {
+                    return Ok(Some(serde_json::from_str(&self.line)?));
+                }
+            },
+            LogFormat::Binary => {
+                if self.reader.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                bincode::deserialize_from(&mut self.reader)
+                    .map(Some)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for EventReader<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+// Checks that time never goes backwards and that every event only refers to
+// participants which exist at that point of the run.
+#[derive(Default)]
+pub struct ConsistencyCheck {
+    time: usize,
+    participants: HashSet<Id>,
+}
+
+impl ConsistencyCheck {
+    fn exists(&self, id: &Id) -> bool {
+        self.participants.contains(id)
+    }
+
+    fn reason(&self, event: &Event) -> Option<String> {
+        if event.time < self.time {
+            return Some(format!("time goes back from {}", self.time));
+        }
+        let unknown = |id: &Id| format!("participant {:?} does not exist", id);
+        let duplicate = |id: &Id| format!("participant {:?} already exists", id);
+        match &event.info {
+            Info::ParticipantCreated { participant_id, .. } => {
+                if self.exists(participant_id) {
+                    return Some(duplicate(participant_id));
+                }
+            }
+            Info::StakeChange { participant_id, .. }
+            | Info::ParticipantBankrupt { participant_id }
+            | Info::ProductionMissed { participant_id, .. }
+            | Info::KickedOut { participant_id }
+            | Info::ValueEstimates { participant_id, .. }
+            | Info::ShardAssigned { participant_id, .. }
+            | Info::LiquidChange { participant_id, .. }
+            | Info::UnstakeRequested { participant_id, .. }
+            | Info::Withdrawn { participant_id, .. } => {
+                if !self.exists(participant_id) {
+                    return Some(unknown(participant_id));
+                }
+            }
+            Info::RoleChange {
+                participant_id,
+                new_role,
+            } => {
+                if !self.exists(participant_id) {
+                    return Some(unknown(participant_id));
+                }
+                if let Some(Role::Delegator(delegatee_id)) = new_role {
+                    if !self.exists(delegatee_id) {
+                        return Some(format!("delegatee {:?} does not exist", delegatee_id));
+                    }
+                }
+            }
+            Info::Slashed {
+                participant_id,
+                delegator_amounts,
+                ..
+            } => {
+                let delegator_ids = delegator_amounts.iter().map(|(id, _)| id);
+                for id in std::iter::once(participant_id).chain(delegator_ids) {
+                    if !self.exists(id) {
+                        return Some(unknown(id));
+                    }
+                }
+            }
+            Info::SupplyChange { .. } => (),
+            Info::ParticipantsMerged {
+                participant_ids,
+                new_participant_id,
+            } => {
+                for id in [participant_ids.0, participant_ids.1].iter() {
+                    if !self.exists(id) {
+                        return Some(unknown(id));
+                    }
+                }
+                if participant_ids.0 == participant_ids.1 {
+                    return Some("participant merged with itself".to_string());
+                }
+                if self.exists(new_participant_id) {
+                    return Some(duplicate(new_participant_id));
+                }
+            }
+            Info::ParticipantSplit {
+                participant_id,
+                new_participant_ids,
+            } => {
+                if !self.exists(participant_id) {
+                    return Some(unknown(participant_id));
+                }
+                for id in [new_participant_ids.0, new_participant_ids.1].iter() {
+                    if self.exists(id) {
+                        return Some(duplicate(id));
+                    }
+                }
+                if new_participant_ids.0 == new_participant_ids.1 {
+                    return Some("participant split into a single participant".to_string());
+                }
+            }
+        }
+        None
+    }
+}
+
+impl EventConsumer for ConsistencyCheck {
+    fn push(&mut self, event: Event) -> Result<(), SimError> {
+        if let Some(reason) = self.reason(&event) {
+            return Err(SimError::InvalidEvent { event, reason });
+        }
+        self.time = event.time;
+        match event.info {
+            Info::ParticipantCreated { participant_id, .. } => {
+                self.participants.insert(participant_id);
+            }
+            Info::ParticipantsMerged {
+                participant_ids,
+                new_participant_id,
+            } => {
+                self.participants.remove(&participant_ids.0);
+                self.participants.remove(&participant_ids.1);
+                self.participants.insert(new_participant_id);
+            }
+            Info::ParticipantSplit {
+                participant_id,
+                new_participant_ids,
+            } => {
+                self.participants.remove(&participant_id);
+                self.participants.insert(new_participant_ids.0);
+                self.participants.insert(new_participant_ids.1);
+            }
+            Info::ParticipantBankrupt { participant_id } => {
+                self.participants.remove(&participant_id);
+            }
+            Info::StakeChange { .. }
+            | Info::RoleChange { .. }
+            | Info::ProductionMissed { .. }
+            | Info::KickedOut { .. }
+            | Info::Slashed { .. }
+            | Info::ValueEstimates { .. }
+            | Info::ShardAssigned { .. }
+            | Info::LiquidChange { .. }
+            | Info::UnstakeRequested { .. }
+            | Info::Withdrawn { .. }
+            | Info::SupplyChange { .. } => (),
+        }
+        Ok(())
+    }
+}
+
+// Pushes every event of `events` into `consumer`, stopping at the first event which is
+// inconsistent with the ones before it. Returns the number of events replayed.
+pub fn replay<I, T>(events: I, consumer: &mut T) -> Result<usize, SimError>
+where
+    I: IntoIterator<Item = io::Result<Event>>,
+    T: EventConsumer,
+{
+    let mut check = ConsistencyCheck::default();
+    let mut count = 0;
+    for event in events {
+        let event = event?;
+        check.push(event.clone())?;
+        consumer.push(event)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{replay, EventReader};
+    use crate::error::SimError;
+    use crate::event::{Event, EventAccumulator, Info};
+    use crate::event_log::{EventLog, LogFormat};
+    use crate::id::Id;
+    use crate::params::Params;
+    use crate::population::InitialStakes;
+    use crate::sim::Simulation;
+    use crate::strategy::StrategyKind;
+
+    #[test]
+    fn test_replay_reproduces_run() {
+        let params = Params {
+            num_block_producers: 3,
+            num_chunk_only_producers: 5,
+            seed: Some(11),
+            initial_stakes: InitialStakes::Uniform {
+                num_participants: 30,
+                min_stake: 100.0,
+                max_stake: 5000.0,
+            },
+            ..Default::default()
+        };
+        let mut expected = EventAccumulator::default();
+        Simulation::new(params.clone())
+            .unwrap()
+            .run(100, &mut expected)
+            .unwrap();
+
+        for format in [LogFormat::JsonLines, LogFormat::Binary].iter() {
+            let mut log = EventLog::new(Vec::new(), *format);
+            Simulation::new(params.clone())
+                .unwrap()
+                .run(100, &mut log)
+                .unwrap();
+            let bytes = log.finish().unwrap();
+
+            let mut replayed = EventAccumulator::default();
+            let count = replay(EventReader::new(bytes.as_slice(), *format), &mut replayed).unwrap();
+            assert_eq!(count, expected.events.len());
+            assert_eq!(replayed.events, expected.events);
+        }
+    }
+
+    #[test]
+    fn test_inconsistent_log() {
+        let created = |time, id| Event {
+            time,
+            info: Info::ParticipantCreated {
+                participant_id: Id::explicit(id),
+                num_tokens: 1.0,
+                strategy: StrategyKind::default(),
+            },
+        };
+        let merged = Event {
+            time: 2,
+            info: Info::ParticipantsMerged {
+                participant_ids: (Id::explicit(0), Id::explicit(5)),
+                new_participant_id: Id::explicit(6),
+            },
+        };
+
+        let mut replayed = EventAccumulator::default();
+        let result = replay(
+            vec![Ok(created(0, 0)), Ok(created(1, 1)), Ok(merged.clone())],
+            &mut replayed,
+        );
+        match result {
+            Err(SimError::InvalidEvent { event, reason }) => {
+                assert_eq!(event, merged);
+                assert!(reason.contains("does not exist"));
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        // events before the inconsistent one are still replayed
+        assert_eq!(replayed.events.len(), 2);
+
+        let result = replay(vec![Ok(created(3, 0)), Ok(created(1, 1))], &mut replayed);
+        assert!(matches!(result, Err(SimError::InvalidEvent { .. })));
+    }
+
+    #[test]
+    fn test_log_without_strategies() {
+        let log =
+            r#"{"time":0,"info":{"participant_created":{"participant_id":0,"num_tokens":1.0}}}"#;
+        let mut reader = EventReader::new(log.as_bytes(), LogFormat::JsonLines);
+        let event = reader.next().unwrap().unwrap();
+        assert_eq!(
+            event.info,
+            Info::ParticipantCreated {
+                participant_id: Id::explicit(0),
+                num_tokens: 1.0,
+                strategy: StrategyKind::default(),
+            }
+        );
+    }
+}
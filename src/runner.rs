@@ -0,0 +1,300 @@
+use crate::event::{Stats, StatsAccumulator};
+use crate::params::Params;
+use crate::sim::Simulation;
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+// Run `f` on each of the `jobs` using up to `num_threads` worker threads. The results
+// are returned in the same order as the jobs, independent of the number of threads.
+pub fn run_parallel<J, R, F>(jobs: Vec<J>, num_threads: usize, f: F) -> Vec<R>
+where
+    J: Send + 'static,
+    R: Send + 'static,
+    F: Fn(J) -> R + Send + Sync + 'static,
+{
+    let num_jobs = jobs.len();
+    let num_threads = num_threads.max(1).min(num_jobs.max(1));
+    let queue = Arc::new(Mutex::new(jobs.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let (sender, receiver) = mpsc::channel();
+
+    let workers: Vec<_> = (0..num_threads)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let f = Arc::clone(&f);
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                // release the lock before running the job
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some((idx, job)) => sender.send((idx, f(job))).unwrap(),
+                    None => break,
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut results: Vec<Option<R>> = (0..num_jobs).map(|_| None).collect();
+    for (idx, result) in receiver {
+        results[idx] = Some(result);
+    }
+    for worker in workers {
+        if let Err(panic) = worker.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+    results
+        .into_iter()
+        .map(|r| r.expect("Every job must produce a result"))
+        .collect()
+}
+
+// Independent replicates of the same simulation, differing only in their seed.
+#[derive(Debug, Clone)]
+pub struct MonteCarlo {
+    pub params: Params,
+    pub replicates: usize,
+    pub duration: usize,
+    // Only every `sample_interval`-th time step is kept in the aggregate statistics.
+    pub sample_interval: usize,
+    // Percentiles (between 0 and 100) of each stats column to report.
+    pub percentiles: Vec<f64>,
+}
+
+// Summary of one `Stats` column across all replicates at one point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub mean: f64,
+    pub std: f64,
+    pub percentiles: Vec<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateStats {
+    pub seeds: Vec<u64>,
+    pub percentiles: Vec<f64>,
+    pub times: Vec<usize>,
+    // `summaries[i][j]` is the summary of column `Stats::columns()[j]` at `times[i]`.
+    pub summaries: Vec<Vec<Summary>>,
+}
+
+impl MonteCarlo {
+    // Replicate `i` uses seed `params.seed + i`; a random base seed is chosen
+    // if `params.seed` is not set.
+    pub fn seeds(&self) -> Vec<u64> {
+        let base_seed = self.params.seed.unwrap_or_else(rand::random);
+        (0..self.replicates)
+            .map(|i| base_seed.wrapping_add(i as u64))
+            .collect()
+    }
+
+    pub fn run(&self, num_threads: usize) -> io::Result<AggregateStats> {
+        if self.replicates == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "replicates must be at least 1",
+            ));
+        }
+        let seeds = self.seeds();
+        let jobs: Vec<Params> = seeds
+            .iter()
+            .map(|seed| {
+                let mut params = self.params.clone();
+                params.seed = Some(*seed);
+                params
+            })
+            .collect();
+        let duration = self.duration;
+        let sample_interval = self.sample_interval.max(1);
+        let series = run_parallel(jobs, num_threads, move |params| {
+            let mut simulation = Simulation::new(params)?;
+            let mut events = StatsAccumulator::default();
+            simulation.run(duration, &mut events)?;
+            Ok(events.sampled_series(duration, sample_interval))
+        });
+        let series = series
+            .into_iter()
+            .collect::<io::Result<Vec<Vec<Stats>>>>()?;
+
+        let times: Vec<usize> = (0..duration).step_by(sample_interval).collect();
+        let summaries = (0..times.len())
+            .map(|i| {
+                (0..Stats::columns().len())
+                    .map(|j| {
+                        let samples: Vec<f64> = series.iter().map(|s| s[i].values()[j]).collect();
+                        summarize(&samples, &self.percentiles)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Ok(AggregateStats {
+            seeds,
+            percentiles: self.percentiles.clone(),
+            times,
+            summaries,
+        })
+    }
+}
+
+impl AggregateStats {
+    pub fn write_stats<P: AsRef<Path>>(&self, file_name: P) -> io::Result<()> {
+        let mut file = File::create(file_name)?;
+        let mut header = vec!["time".to_string()];
+        for column in Stats::columns() {
+            header.push(format!("{}_mean", column));
+            header.push(format!("{}_std", column));
+            for p in self.percentiles.iter() {
+                header.push(format!("{}_p{}", column, p));
+            }
+        }
+        file.write_all(format!("{}\n", header.join(",")).as_bytes())?;
+
+        for (time, summaries) in self.times.iter().zip(self.summaries.iter()) {
+            let mut line = vec![time.to_string()];
+            for s in summaries {
+                line.push(s.mean.to_string());
+                line.push(s.std.to_string());
+                line.extend(s.percentiles.iter().map(|p| p.to_string()));
+            }
+            file.write_all(format!("{}\n", line.join(",")).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl AggregateStats {
+    // One object per time, with the summaries of each column keyed by column name.
+    pub fn write_stats_json<P: AsRef<Path>>(&self, file_name: P) -> io::Result<()> {
+        let rows: Vec<serde_json::Value> = self
+            .times
+            .iter()
+            .zip(self.summaries.iter())
+            .map(|(time, summaries)| {
+                let mut object = serde_json::Map::new();
+                object.insert("time".to_string(), (*time).into());
+                for (column, s) in Stats::columns().into_iter().zip(summaries.iter()) {
+                    let mut summary = serde_json::Map::new();
+                    summary.insert("mean".to_string(), s.mean.into());
+                    summary.insert("std".to_string(), s.std.into());
+                    for (p, value) in self.percentiles.iter().zip(s.percentiles.iter()) {
+                        summary.insert(format!("p{}", p), (*value).into());
+                    }
+                    object.insert(column, summary.into());
+                }
+                object.into()
+            })
+            .collect();
+        serde_json::to_writer(File::create(file_name)?, &rows)?;
+        Ok(())
+    }
+}
+
+// NaN samples (e.g. a ratio of two empty stakes) are left out. Without any samples left
+// every value of the summary is NaN.
+fn summarize(samples: &[f64], percentiles: &[f64]) -> Summary {
+    let mut sorted: Vec<f64> = samples.iter().copied().filter(|x| !x.is_nan()).collect();
+    sorted.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = sorted.len() as f64;
+    let mean = sorted.iter().sum::<f64>() / n;
+    let variance = if sorted.len() > 1 {
+        sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)
+    } else if sorted.len() == 1 {
+        0.0
+    } else {
+        f64::NAN
+    };
+    Summary {
+        mean,
+        std: variance.sqrt(),
+        percentiles: percentiles
+            .iter()
+            .map(|p| percentile(&sorted, *p))
+            .collect(),
+    }
+}
+
+// Linear interpolation between the closest ranks of the sorted samples.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let rank = (p / 100.0).max(0.0).min(1.0) * ((sorted.len() - 1) as f64);
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_parallel, summarize, MonteCarlo};
+    use crate::params::Params;
+    use crate::population::InitialStakes;
+
+    #[test]
+    fn test_run_parallel_preserves_order() {
+        let jobs: Vec<u64> = (0..100).collect();
+        let expected: Vec<u64> = jobs.iter().map(|x| x * x).collect();
+        for num_threads in 1..5 {
+            assert_eq!(run_parallel(jobs.clone(), num_threads, |x| x * x), expected);
+        }
+        assert!(run_parallel(Vec::<u64>::new(), 4, |x| x).is_empty());
+    }
+
+    #[test]
+    fn test_summarize() {
+        let summary = summarize(&[4.0, 1.0, 3.0, 2.0], &[0.0, 50.0, 100.0]);
+        assert_eq!(summary.mean, 2.5);
+        assert!((summary.std - (5.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert_eq!(summary.percentiles, vec![1.0, 2.5, 4.0]);
+
+        let summary = summarize(&[f64::NAN, 2.0, 4.0], &[50.0]);
+        assert_eq!(summary.mean, 3.0);
+        assert_eq!(summary.percentiles, vec![3.0]);
+        let summary = summarize(&[], &[50.0]);
+        assert!(summary.mean.is_nan() && summary.std.is_nan() && summary.percentiles[0].is_nan());
+    }
+
+    #[test]
+    fn test_monte_carlo_independent_of_threads() {
+        let monte_carlo = MonteCarlo {
+            params: Params {
+                num_block_producers: 2,
+                num_chunk_only_producers: 2,
+                chunk_only_producer_cost: 5.0,
+                block_producer_cost_factor: 7.0,
+                total_reward: 3000.0,
+                block_producer_reward_fraction: 0.6,
+                block_producer_delegation_fee: 0.15,
+                chunk_only_producer_delegation_fee: 0.05,
+                seed: Some(3),
+                initial_stakes: InitialStakes::Uniform {
+                    num_participants: 20,
+                    min_stake: 100.0,
+                    max_stake: 5000.0,
+                },
+                ..Default::default()
+            },
+            replicates: 6,
+            duration: 100,
+            sample_interval: 10,
+            percentiles: vec![5.0, 50.0, 95.0],
+        };
+        let single = monte_carlo.run(1).unwrap();
+        let multi = monte_carlo.run(4).unwrap();
+        assert_eq!(single.seeds, vec![3, 4, 5, 6, 7, 8]);
+        assert_eq!(single.times.len(), 10);
+        assert_eq!(single, multi);
+
+        let empty = MonteCarlo {
+            replicates: 0,
+            ..monte_carlo
+        };
+        assert!(empty.run(1).is_err());
+    }
+}
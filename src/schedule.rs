@@ -0,0 +1,215 @@
+use crate::params::Validator;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// How a field of `Params` changes over the course of a run. Before a schedule starts
+// the field keeps the value given in the params.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Schedule {
+    // The field takes each `value` from its `time` on.
+    Steps {
+        changes: Vec<Change>,
+    },
+    // The field changes linearly from `from` at `start_time` to `to` at `end_time`, and
+    // stays at `to` afterwards.
+    Ramp {
+        start_time: usize,
+        end_time: usize,
+        from: f64,
+        to: f64,
+    },
+    // The field is multiplied by `multiplier` at the start of every epoch of
+    // `epoch_length` time steps, e.g. to model inflation.
+    EpochMultiplier {
+        epoch_length: usize,
+        multiplier: f64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Change {
+    pub time: usize,
+    pub value: Value,
+}
+
+impl Schedule {
+    // Value at `time` of a field whose value in the params is `base`. `None` if the
+    // schedule computes a number but `base` is not one.
+    pub fn value_at(&self, base: &Value, time: usize) -> Option<Value> {
+        match self {
+            Self::Steps { changes } => Some(
+                changes
+                    .iter()
+                    .rev()
+                    .find(|c| c.time <= time)
+                    .map_or_else(|| base.clone(), |c| c.value.clone()),
+            ),
+            Self::Ramp {
+                start_time,
+                end_time,
+                from,
+                to,
+            } => {
+                if time < *start_time {
+                    return Some(base.clone());
+                }
+                let x = if time >= *end_time {
+                    *to
+                } else {
+                    from + (to - from) * ((time - start_time) as f64)
+                        / ((end_time - start_time) as f64)
+                };
+                number_like(base, x)
+            }
+            Self::EpochMultiplier {
+                epoch_length,
+                multiplier,
+            } => {
+                let epochs = (time / epoch_length).min(i32::MAX as usize) as i32;
+                let base_value = base.as_f64()?;
+                // a value growing without bound is capped at the largest finite number
+                let x = if base_value == 0.0 {
+                    0.0
+                } else {
+                    (base_value * multiplier.powi(epochs))
+                        .max(-f64::MAX)
+                        .min(f64::MAX)
+                };
+                number_like(base, x)
+            }
+        }
+    }
+
+    // Times at which the value of the field jumps.
+    pub fn breakpoints(&self) -> Vec<usize> {
+        match self {
+            Self::Steps { changes } => changes.iter().map(|c| c.time).collect(),
+            Self::Ramp {
+                start_time,
+                end_time,
+                ..
+            } => vec![*start_time, *end_time],
+            Self::EpochMultiplier { epoch_length, .. } => vec![*epoch_length],
+        }
+    }
+
+    // Whether `time` is one of the `breakpoints`, or any later time at which an epoch
+    // multiplier applies again.
+    pub fn is_breakpoint(&self, time: usize) -> bool {
+        match self {
+            Self::EpochMultiplier { epoch_length, .. } => time % epoch_length == 0,
+            _ => self.breakpoints().contains(&time),
+        }
+    }
+
+    pub fn validate(&self, field: &str, v: &mut Validator) {
+        match self {
+            Self::Steps { changes } => v.check(
+                changes.windows(2).all(|w| w[0].time < w[1].time),
+                &format!("{}.changes", field),
+                "must be in order of time",
+            ),
+            Self::Ramp {
+                start_time,
+                end_time,
+                from,
+                to,
+            } => {
+                v.check(
+                    end_time >= start_time,
+                    &format!("{}.end_time", field),
+                    "must not be before start_time",
+                );
+                v.check(
+                    from.is_finite() && to.is_finite(),
+                    field,
+                    "from and to must be finite numbers",
+                );
+            }
+            Self::EpochMultiplier {
+                epoch_length,
+                multiplier,
+            } => {
+                v.check(
+                    *epoch_length > 0,
+                    &format!("{}.epoch_length", field),
+                    "must be at least 1",
+                );
+                v.non_negative(*multiplier, &format!("{}.multiplier", field));
+            }
+        }
+    }
+}
+
+// `x` as a JSON number, rounded if the field holds an integer.
+fn number_like(base: &Value, x: f64) -> Option<Value> {
+    if !base.is_number() {
+        None
+    } else if base.is_u64() && x >= 0.0 {
+        Some(Value::from(x.round() as u64))
+    } else if base.is_i64() || base.is_u64() {
+        Some(Value::from(x.round() as i64))
+    } else {
+        serde_json::Number::from_f64(x).map(Value::Number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Schedule;
+    use serde_json::json;
+
+    #[test]
+    fn test_value_at() {
+        let steps: Schedule = serde_json::from_value(json!({
+            "kind": "steps",
+            "changes": [{"time": 10, "value": 120}, {"time": 20, "value": 150}]
+        }))
+        .unwrap();
+        let values: Vec<_> = [0, 10, 19, 20, 100]
+            .iter()
+            .map(|t| steps.value_at(&json!(100), *t).unwrap())
+            .collect();
+        assert_eq!(
+            values,
+            vec![json!(100), json!(120), json!(120), json!(150), json!(150)]
+        );
+
+        let ramp = Schedule::Ramp {
+            start_time: 10,
+            end_time: 20,
+            from: 1.0,
+            to: 0.5,
+        };
+        assert_eq!(ramp.value_at(&json!(0.2), 5), Some(json!(0.2)));
+        assert_eq!(ramp.value_at(&json!(0.2), 15), Some(json!(0.75)));
+        assert_eq!(ramp.value_at(&json!(0.2), 25), Some(json!(0.5)));
+        // integer fields stay integers
+        assert_eq!(ramp.value_at(&json!(3), 15), Some(json!(1)));
+        assert_eq!(ramp.value_at(&json!("a"), 15), None);
+
+        let inflation = Schedule::EpochMultiplier {
+            epoch_length: 100,
+            multiplier: 0.5,
+        };
+        assert_eq!(inflation.value_at(&json!(3000.0), 99), Some(json!(3000.0)));
+        assert_eq!(inflation.value_at(&json!(3000.0), 250), Some(json!(750.0)));
+        assert!(inflation.is_breakpoint(300) && !inflation.is_breakpoint(250));
+        let growth = Schedule::EpochMultiplier {
+            epoch_length: 10,
+            multiplier: 1.5,
+        };
+        assert_eq!(
+            growth.value_at(&json!(0.5), usize::MAX),
+            Some(json!(f64::MAX))
+        );
+        assert_eq!(
+            inflation.value_at(&json!(3000.0), usize::MAX),
+            Some(json!(0.0))
+        );
+        assert!(steps.is_breakpoint(20) && !steps.is_breakpoint(15));
+    }
+}
@@ -0,0 +1,370 @@
+use crate::error::SimError;
+use crate::event::{Event, EventConsumer, Info};
+use crate::id::Id;
+use crate::role::Role;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+// Fractions of the stake of a shard (or of the BP set) an attacker has to control to
+// break liveness (1/3) or safety (2/3) of it.
+const THRESHOLDS: [(&str, f64); 2] = [("1_3", 1.0 / 3.0), ("2_3", 2.0 / 3.0)];
+
+// Tracks how much stake it takes to take over each shard and the BP set. Stake counts
+// towards the producer it is delegated to. COPs start out in shard 0.
+#[derive(Default)]
+pub struct SecurityMetrics {
+    // shards which are reported even if no COP is assigned to them
+    num_shards: usize,
+    history: Vec<Security>,
+    current: Security,
+    stakes: BTreeMap<Id, f64>,
+    roles: BTreeMap<Id, Role>,
+    shards: BTreeMap<Id, usize>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Security {
+    time: usize,
+    bp: Takeover,
+    shards: Vec<Takeover>,
+}
+
+// For each of the `THRESHOLDS`: the fraction of all staked tokens needed to control
+// that fraction of the stake, and the smallest number of producers which control it.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Takeover {
+    stake_share: [f64; THRESHOLDS.len()],
+    num_producers: [usize; THRESHOLDS.len()],
+}
+
+impl Takeover {
+    fn new(mut stakes: Vec<f64>, total_stake: f64) -> Self {
+        stakes.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        let stake: f64 = stakes.iter().sum();
+        let mut takeover = Self::default();
+        for (i, (_, threshold)) in THRESHOLDS.iter().enumerate() {
+            if stake > 0.0 {
+                takeover.stake_share[i] = threshold * stake / total_stake;
+            }
+            let mut controlled = 0f64;
+            takeover.num_producers[i] = stakes
+                .iter()
+                .take_while(|s| {
+                    let enough = controlled >= threshold * stake;
+                    controlled += *s;
+                    !enough
+                })
+                .count();
+        }
+        takeover
+    }
+
+    fn values(&self) -> Vec<f64> {
+        let mut values = Vec::new();
+        for i in 0..THRESHOLDS.len() {
+            values.push(self.stake_share[i]);
+            values.push(self.num_producers[i] as f64);
+        }
+        values
+    }
+}
+
+impl Security {
+    fn columns(num_shards: usize) -> Vec<String> {
+        let mut columns = Vec::new();
+        let prefixes = std::iter::once("bp".to_string())
+            .chain((0..num_shards).map(|shard| format!("shard_{}", shard)));
+        for prefix in prefixes {
+            for (name, _) in THRESHOLDS.iter() {
+                columns.push(format!("{}_takeover_stake_{}", prefix, name));
+                columns.push(format!("{}_takeover_producers_{}", prefix, name));
+            }
+        }
+        columns
+    }
+
+    // Shards beyond `shards` (e.g. a shard only assigned later) have no stake.
+    fn values(&self, num_shards: usize) -> Vec<f64> {
+        let mut values = self.bp.values();
+        for shard in 0..num_shards {
+            match self.shards.get(shard) {
+                Some(takeover) => values.extend(takeover.values()),
+                None => values.extend(Takeover::default().values()),
+            }
+        }
+        values
+    }
+}
+
+impl SecurityMetrics {
+    pub fn new(num_shards: usize) -> Self {
+        Self {
+            num_shards,
+            ..Default::default()
+        }
+    }
+
+    pub fn write_metrics<P: AsRef<Path>>(&mut self, file_name: P) -> io::Result<()> {
+        self.compute();
+        let num_shards = self.num_shards();
+        let mut file = File::create(file_name)?;
+        let header = Security::columns(num_shards).join(",");
+        file.write_all(format!("time,{}\n", header).as_bytes())?;
+        for s in self.history.iter().chain(std::iter::once(&self.current)) {
+            let values: Vec<String> = s.values(num_shards).iter().map(|v| v.to_string()).collect();
+            file.write_all(format!("{},{}\n", s.time, values.join(",")).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Same content as `write_metrics`, as a JSON array with one object per time.
+    pub fn write_metrics_json<P: AsRef<Path>>(&mut self, file_name: P) -> io::Result<()> {
+        self.compute();
+        let num_shards = self.num_shards();
+        let rows: Vec<serde_json::Value> = self
+            .history
+            .iter()
+            .chain(std::iter::once(&self.current))
+            .map(|s| {
+                let mut object = serde_json::Map::new();
+                object.insert("time".to_string(), s.time.into());
+                for (column, value) in Security::columns(num_shards)
+                    .into_iter()
+                    .zip(s.values(num_shards))
+                {
+                    object.insert(column, value.into());
+                }
+                object.into()
+            })
+            .collect();
+        serde_json::to_writer(File::create(file_name)?, &rows)?;
+        Ok(())
+    }
+
+    fn num_shards(&self) -> usize {
+        self.history
+            .iter()
+            .chain(std::iter::once(&self.current))
+            .map(|s| s.shards.len())
+            .fold(self.num_shards, usize::max)
+    }
+
+    fn compute(&mut self) {
+        let mut effective_stakes: BTreeMap<Id, f64> = BTreeMap::new();
+        for (id, stake) in self.stakes.iter() {
+            let producer_id = match self.roles.get(id) {
+                Some(Role::Delegator(delegatee_id)) => *delegatee_id,
+                Some(_) => *id,
+                None => continue,
+            };
+            match self.roles.get(&producer_id) {
+                Some(Role::BlockProducer) | Some(Role::ChunkOnlyProducer) => {
+                    *effective_stakes.entry(producer_id).or_insert(0f64) += stake;
+                }
+                None | Some(Role::Delegator(_)) => (),
+            }
+        }
+
+        let total_stake: f64 = effective_stakes.values().sum();
+        let mut bp_stakes = Vec::new();
+        let mut shard_stakes: Vec<Vec<f64>> = vec![Vec::new(); self.num_shards];
+        for (id, stake) in effective_stakes {
+            if self.roles.get(&id) == Some(&Role::BlockProducer) {
+                bp_stakes.push(stake);
+            } else {
+                let shard = self.shards.get(&id).copied().unwrap_or(0);
+                if shard_stakes.len() <= shard {
+                    shard_stakes.resize(shard + 1, Vec::new());
+                }
+                shard_stakes[shard].push(stake);
+            }
+        }
+        self.current.bp = Takeover::new(bp_stakes, total_stake);
+        self.current.shards = shard_stakes
+            .into_iter()
+            .map(|stakes| Takeover::new(stakes, total_stake))
+            .collect();
+    }
+
+    fn unknown(event: Event, id: &Id) -> SimError {
+        SimError::InvalidEvent {
+            reason: format!("participant {:?} was never created", id),
+            event,
+        }
+    }
+}
+
+impl EventConsumer for SecurityMetrics {
+    fn push(&mut self, e: Event) -> Result<(), SimError> {
+        if e.time != self.current.time {
+            self.compute();
+            self.history.push(self.current.clone());
+            self.current.time = e.time;
+        }
+
+        match e.info {
+            Info::ParticipantCreated {
+                participant_id,
+                num_tokens,
+                ..
+            } => {
+                self.stakes.insert(participant_id, num_tokens);
+            }
+            Info::StakeChange {
+                participant_id,
+                change_amount,
+            } => match self.stakes.get_mut(&participant_id) {
+                Some(stake) => *stake += change_amount,
+                None => return Err(Self::unknown(e, &participant_id)),
+            },
+            Info::Slashed {
+                participant_id,
+                amount,
+                ref delegator_amounts,
+            } => {
+                let own_amount = (participant_id, amount);
+                for (id, amount) in std::iter::once(&own_amount).chain(delegator_amounts.iter()) {
+                    match self.stakes.get_mut(id) {
+                        Some(stake) => *stake -= amount,
+                        None => return Err(Self::unknown(e.clone(), id)),
+                    }
+                }
+            }
+            Info::RoleChange {
+                participant_id,
+                new_role,
+            } => match new_role {
+                None => {
+                    self.roles.remove(&participant_id);
+                }
+                Some(role) => {
+                    self.roles.insert(participant_id, role);
+                }
+            },
+            Info::ShardAssigned {
+                participant_id,
+                shard,
+            } => {
+                self.shards.insert(participant_id, shard);
+            }
+            Info::ParticipantsMerged {
+                participant_ids,
+                new_participant_id,
+            } => {
+                let (id0, id1) = participant_ids;
+                let stake = self.stakes.remove(&id0).unwrap_or(0.0)
+                    + self.stakes.remove(&id1).unwrap_or(0.0);
+                self.stakes.insert(new_participant_id, stake);
+                // the merged participant keeps the role and shard of the first one
+                if let Some(role) = self.roles.remove(&id0) {
+                    self.roles.insert(new_participant_id, role);
+                }
+                self.roles.remove(&id1);
+                if let Some(shard) = self.shards.remove(&id0) {
+                    self.shards.insert(new_participant_id, shard);
+                }
+                self.shards.remove(&id1);
+            }
+            Info::ParticipantSplit {
+                participant_id,
+                new_participant_ids,
+            } => {
+                let new_ids = [new_participant_ids.0, new_participant_ids.1];
+                if let Some(stake) = self.stakes.remove(&participant_id) {
+                    for id in new_ids.iter() {
+                        self.stakes.insert(*id, stake / 2.0);
+                    }
+                }
+                if let Some(role) = self.roles.remove(&participant_id) {
+                    for id in new_ids.iter() {
+                        self.roles.insert(*id, role);
+                    }
+                }
+                if let Some(shard) = self.shards.remove(&participant_id) {
+                    for id in new_ids.iter() {
+                        self.shards.insert(*id, shard);
+                    }
+                }
+            }
+            Info::ParticipantBankrupt { participant_id } => {
+                self.stakes.remove(&participant_id);
+                self.roles.remove(&participant_id);
+                self.shards.remove(&participant_id);
+            }
+            Info::ProductionMissed { .. }
+            | Info::KickedOut { .. }
+            | Info::ValueEstimates { .. }
+            | Info::LiquidChange { .. }
+            | Info::UnstakeRequested { .. }
+            | Info::Withdrawn { .. }
+            | Info::SupplyChange { .. } => (),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SecurityMetrics, Takeover};
+    use crate::event::{Event, EventConsumer, Info};
+    use crate::id::Id;
+    use crate::role::Role;
+    use crate::strategy::StrategyKind;
+
+    #[test]
+    fn test_takeover() {
+        let takeover = Takeover::new(vec![10.0, 50.0, 20.0, 20.0], 200.0);
+        assert_eq!(takeover.stake_share[0], 1.0 / 3.0 * 100.0 / 200.0);
+        // the largest producer alone has more than 1/3, it takes two for 2/3
+        assert_eq!(takeover.num_producers, [1, 2]);
+        assert_eq!(Takeover::new(Vec::new(), 0.0), Takeover::default());
+    }
+
+    #[test]
+    fn test_shards() {
+        let mut metrics = SecurityMetrics::new(3);
+        let ids: Vec<Id> = (0..4).map(Id::explicit).collect();
+        let mut infos = Vec::new();
+        for (id, stake) in ids.iter().zip([100.0, 40.0, 60.0, 20.0].iter()) {
+            infos.push(Info::ParticipantCreated {
+                participant_id: *id,
+                num_tokens: *stake,
+                strategy: StrategyKind::default(),
+            });
+        }
+        let roles = [
+            Role::BlockProducer,
+            Role::ChunkOnlyProducer,
+            Role::ChunkOnlyProducer,
+            Role::Delegator(ids[1]),
+        ];
+        for (id, role) in ids.iter().zip(roles.iter()) {
+            infos.push(Info::RoleChange {
+                participant_id: *id,
+                new_role: Some(*role),
+            });
+        }
+        infos.push(Info::ShardAssigned {
+            participant_id: ids[2],
+            shard: 1,
+        });
+        for info in infos {
+            metrics.push(Event { time: 0, info }).unwrap();
+        }
+        metrics.compute();
+
+        let current = &metrics.current;
+        assert_eq!(current.bp.stake_share[1], 2.0 / 3.0 * 100.0 / 220.0);
+        assert_eq!(current.shards.len(), 3);
+        // shard 0 has the COP with 40 tokens and 20 delegated to it
+        assert_eq!(current.shards[0].stake_share[0], 1.0 / 3.0 * 60.0 / 220.0);
+        assert_eq!(current.shards[1].num_producers, [1, 1]);
+        // no COP is assigned to shard 2, so it takes nothing to take it over
+        assert_eq!(current.shards[2], Takeover::default());
+        assert_eq!(metrics.num_shards(), 3);
+        assert_eq!(SecurityMetrics::new(3).num_shards(), 3);
+    }
+}
@@ -1,72 +1,317 @@
+use crate::error::SimError;
 use crate::event::{self, Event, EventConsumer};
 use crate::id::{Id, IdGenerator};
+use crate::params::{DanglingDelegationPolicy, Params, ParamsError, SelectionMode};
 use crate::role::Role;
+use crate::strategy::{
+    self, Market, Observation, ParticipantStrategy, Proposal, Strategy, StrategyKind,
+};
 
-use rand::Rng;
-
+use indexmap::IndexMap;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use rand_distr::{Binomial, Distribution};
 use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
-use std::hash::BuildHasher;
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Params {
-    pub num_block_producers: usize,
-    pub num_chunk_only_producers: usize,
-    pub chunk_only_producer_cost: f64,
-    pub block_producer_cost_factor: f64,
-    pub total_reward: f64,
-    pub block_producer_reward_fraction: f64,
-    pub block_producer_delegation_fee: f64,
-    pub chunk_only_producer_delegation_fee: f64,
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+// Participants are iterated in insertion order (removing one moves the last participant
+// into its place). Unlike the order of a HashMap this survives a checkpoint, so the whole
+// run is deterministic for a given seed, also when it is resumed.
+type ParticipantMap = IndexMap<Id, Participant>;
+
+// Participant present at the start of the simulation. The `i`-th initial participant
+// is given `Id::explicit(i)`, so delegators can refer to other initial participants.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InitialParticipant {
+    pub num_tokens: f64,
+    pub role: Option<Role>,
+}
+
+// Number of participants in each role at a point in the simulation.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ParticipantCounts {
+    pub block_producers: usize,
+    pub chunk_only_producers: usize,
+    pub delegators: usize,
+    pub total: usize,
 }
 
+// The complete state of a run; serializing it gives a checkpoint the run can be
+// resumed from.
+#[derive(Serialize, Deserialize)]
 pub struct Simulation {
-    participants: HashMap<Id, Participant>,
+    participants: ParticipantMap,
     params: Params,
     id_generator: IdGenerator,
+    // same algorithm as `StdRng`, but its state can be serialized
+    rng: ChaCha12Rng,
+    // first time step which has not been simulated yet; time 0 is the initial state
+    time: usize,
+    // roles selected at the end of an epoch which have not taken effect yet
+    pending_roles: Vec<PendingRoles>,
+    // total token supply; only tracked if the rewards depend on it
+    supply: Option<f64>,
+    // `params` with the schedules applied at the last epoch end or schedule breakpoint;
+    // `None` if there are no schedules
+    scheduled_params: Option<Params>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Unstaking {
+    // number of epoch ends left before the tokens are withdrawn
+    epochs_left: usize,
+    amount: f64,
+    // whether the tokens go to the liquid balance or back to `num_tokens`
+    to_liquid: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PendingRoles {
+    // number of epoch ends left before the roles take effect
+    epochs_left: usize,
+    roles: Vec<SelectedRole>,
+}
+
+// The role selected for a participant at an epoch end.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct SelectedRole {
+    id: Id,
+    role: Option<Role>,
+    // whether the participant chose the role itself by delegating or staying out, rather
+    // than being assigned it by the selection; only then is its stake unstaked to move
+    chosen: bool,
+}
+
+impl SelectedRole {
+    fn new(id: Id, role: Option<Role>, chosen: bool) -> Self {
+        Self { id, role, chosen }
+    }
 }
 
 impl Simulation {
-    pub fn new(initial_stakes: &[f64], params: Params) -> Self {
+    // The initial stakes are drawn according to `params.initial_stakes`, using
+    // the same seeded RNG as the rest of the run.
+    pub fn new(params: Params) -> io::Result<Self> {
+        params
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut rng = match params.seed {
+            Some(seed) => ChaCha12Rng::seed_from_u64(seed),
+            None => ChaCha12Rng::from_entropy(),
+        };
+        let initial_participants = params.initial_stakes.generate(&mut rng)?;
+        let stakes: Vec<f64> = initial_participants.iter().map(|p| p.num_tokens).collect();
+        let strategies =
+            strategy::assign_strategies(&stakes, &params.strategy_mix, params.strategy, &mut rng);
         let mut id_generator = IdGenerator::default();
-        let participants = initial_stakes
+        let participants = initial_participants
             .iter()
-            .map(|stake| {
-                let p = Participant::new(&mut id_generator, *stake);
+            .zip(strategies)
+            .map(|(initial, strategy)| {
+                let mut p = Participant::new(&mut id_generator, initial.num_tokens);
+                p.role = initial.role;
+                p.reliability = params.draw_reliability(&mut rng);
+                p.set_strategy(strategy, &params);
+                p.keep_liquid(params.liquid_fraction);
                 (p.id, p)
             })
             .collect();
-        Self {
+        Ok(Self {
             participants,
             params,
             id_generator,
-        }
+            rng,
+            time: 1,
+            pending_roles: Vec::new(),
+            supply: None,
+            scheduled_params: None,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(file_name: P) -> io::Result<Self> {
+        let simulation: Self = serde_json::from_reader(BufReader::new(File::open(file_name)?))?;
+        simulation
+            .params
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(simulation)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, file_name: P) -> io::Result<()> {
+        serde_json::to_writer(BufWriter::new(File::create(file_name)?), self)?;
+        Ok(())
+    }
+
+    pub fn time(&self) -> usize {
+        self.time
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    // Replaces the params for all time steps which have not been simulated yet.
+    // `initial_stakes` and `seed` no longer have any effect once the run has started.
+    pub fn set_params(&mut self, params: Params) -> Result<(), ParamsError> {
+        params.validate()?;
+        self.params = params;
+        self.scheduled_params = None;
+        Ok(())
     }
 
-    pub fn run<T: EventConsumer>(&mut self, duration: usize, events: &mut T) {
-        // record creation of initial set of participants
+    // Simulates the time steps before `duration` which have not been simulated yet. The
+    // participants present before the first of these steps are reported to `events`
+    // first, so a run resumed from a checkpoint can be fed into fresh consumers.
+    //
+    // Participants come and go at every time step, but rewards are only paid at the end
+    // of an epoch (every `epoch_length` steps). The participants then propose their
+    // roles for the next epoch, the proposals are selected, and the selected roles are
+    // activated `activation_delay - 1` epoch ends later.
+    pub fn run<T: EventConsumer>(
+        &mut self,
+        duration: usize,
+        events: &mut T,
+    ) -> Result<(), SimError> {
+        // record the participants present at the start
+        let start_time = self.time - 1;
         for p in self.participants.values() {
             events.push(Event {
-                time: 0,
+                time: start_time,
                 info: event::Info::ParticipantCreated {
                     participant_id: p.id,
                     num_tokens: p.num_tokens,
+                    strategy: p.strategy.kind(),
                 },
-            })
+            })?;
+            if p.liquid_tokens > 0.0 {
+                events.push(Event {
+                    time: start_time,
+                    info: event::Info::LiquidChange {
+                        participant_id: p.id,
+                        change_amount: p.liquid_tokens,
+                    },
+                })?;
+            }
+        }
+        for p in self.participants.values() {
+            if p.role.is_some() {
+                events.push(Event {
+                    time: start_time,
+                    info: event::Info::RoleChange {
+                        participant_id: p.id,
+                        new_role: p.role,
+                    },
+                })?;
+            }
         }
-        let mut rng = rand::thread_rng();
-        for time in 1..duration {
-            update_token_amounts(&mut self.participants, &self.params, time, events);
+        for time in self.time..duration {
+            if !self.params.schedules.is_empty() {
+                let stale = match &self.scheduled_params {
+                    Some(params) => {
+                        time % params.epoch_length == 0
+                            || self
+                                .params
+                                .schedules
+                                .values()
+                                .any(|s| s.is_breakpoint(time))
+                    }
+                    None => true,
+                };
+                if stale {
+                    let params = self
+                        .params
+                        .at(time)
+                        .map_err(|error| SimError::InvalidParams { time, error })?;
+                    self.scheduled_params = Some(params.into_owned());
+                }
+            }
+            let mut params = Cow::Borrowed(self.scheduled_params.as_ref().unwrap_or(&self.params));
+            let end_of_epoch = time % params.epoch_length == 0;
+            let kicked_out = if end_of_epoch {
+                // if the rewards come from inflation, the reward pool replaces `total_reward`
+                let supply = params
+                    .reward_model
+                    .initial_supply()
+                    .map(|initial_supply| self.supply.unwrap_or(initial_supply));
+                let epoch_supply = supply.and_then(|s| {
+                    let staked = staked_tokens(&self.participants);
+                    params.reward_model.epoch(s, staked, params.epoch_length)
+                });
+                if let Some(epoch_supply) = &epoch_supply {
+                    params.to_mut().total_reward =
+                        epoch_supply.reward_pool / (params.epoch_length as f64);
+                }
+                let (kicked_out, distributed, slashed) =
+                    settle_rewards(&mut self.participants, &params, time, events, &mut self.rng)?;
+                settle_balances(&mut self.participants, &params, time, events)?;
+                if let (Some(supply), Some(epoch_supply)) = (supply, epoch_supply) {
+                    let new_supply = supply + epoch_supply.minted - epoch_supply.burned - slashed;
+                    self.supply = Some(new_supply);
+                    // the rewards which were not paid out go to the treasury
+                    let undistributed = (epoch_supply.reward_pool - distributed).max(0.0);
+                    events.push(Event {
+                        time,
+                        info: event::Info::SupplyChange {
+                            total_supply: new_supply,
+                            minted: epoch_supply.minted,
+                            treasury: epoch_supply.treasury + undistributed,
+                            burned: epoch_supply.burned + slashed,
+                            reward_pool: epoch_supply.reward_pool,
+                        },
+                    })?;
+                }
+                kicked_out
+            } else {
+                Vec::new()
+            };
             manage_participants(
                 &mut self.participants,
+                &params,
                 time,
                 events,
                 &mut self.id_generator,
-                &mut rng,
-            );
-            update_roles(&mut self.participants, &self.params, time, events, &mut rng);
+                &mut self.rng,
+            )?;
+            if end_of_epoch {
+                for pending in self.pending_roles.iter_mut() {
+                    pending.epochs_left -= 1;
+                }
+                self.pending_roles.push(PendingRoles {
+                    epochs_left: params.activation_delay - 1,
+                    roles: select_roles(
+                        &mut self.participants,
+                        &params,
+                        time,
+                        events,
+                        &mut self.rng,
+                    )?,
+                });
+                let mut i = 0;
+                while i < self.pending_roles.len() {
+                    if self.pending_roles[i].epochs_left == 0 {
+                        let pending = self.pending_roles.remove(i);
+                        activate_roles(
+                            &mut self.participants,
+                            &params,
+                            &pending.roles,
+                            &kicked_out,
+                            time,
+                            events,
+                        )?;
+                    } else {
+                        i += 1;
+                    }
+                }
+                assign_shards(&mut self.participants, &params, time, events)?;
+            }
+            self.time = time + 1;
         }
+        Ok(())
     }
 
     pub fn stake_fraction(&self) -> f64 {
@@ -76,7 +321,7 @@ impl Simulation {
             match &p.role {
                 Some(Role::BlockProducer) => total_bp_stake += p.num_tokens,
                 Some(Role::ChunkOnlyProducer) => total_cop_stake += p.num_tokens,
-                Some(Role::Delegator(id)) => match &self.participants.get(id).unwrap().role {
+                Some(Role::Delegator(id)) => match self.participants.get(id).and_then(|d| d.role) {
                     Some(Role::BlockProducer) => total_bp_stake += p.num_tokens,
                     Some(Role::ChunkOnlyProducer) => total_cop_stake += p.num_tokens,
                     None | Some(Role::Delegator(_)) => (),
@@ -86,18 +331,43 @@ impl Simulation {
         }
         total_cop_stake / total_bp_stake
     }
+
+    pub fn participant_counts(&self) -> ParticipantCounts {
+        let mut counts = ParticipantCounts {
+            total: self.participants.len(),
+            ..Default::default()
+        };
+        for p in self.participants.values() {
+            match &p.role {
+                Some(Role::BlockProducer) => counts.block_producers += 1,
+                Some(Role::ChunkOnlyProducer) => counts.chunk_only_producers += 1,
+                Some(Role::Delegator(_)) => counts.delegators += 1,
+                None => (),
+            }
+        }
+        counts
+    }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 struct Participant {
     id: Id,
     num_tokens: f64,
     // BP, COP, or None if insufficient stake to be BP or COP
     role: Option<Role>,
+    // shard the participant produces chunks for if it is a COP
+    shard: usize,
+    // tokens which are not staked, in addition to `num_tokens`
+    liquid_tokens: f64,
+    // tokens which were unstaked and are not withdrawn yet
+    unstaking: Vec<Unstaking>,
     // actual stake change
     most_recent_stake_change: f64,
     // expected stake change if we switch roles
     expected_stake_change_on_switch: f64,
+    // probability to produce an assigned block (chunk)
+    reliability: f64,
+    strategy: ParticipantStrategy,
 }
 
 impl Participant {
@@ -106,11 +376,59 @@ impl Participant {
             id: id_generator.next(),
             num_tokens,
             role: None,
+            shard: 0,
+            liquid_tokens: 0f64,
+            unstaking: Vec::new(),
             most_recent_stake_change: 0f64,
             expected_stake_change_on_switch: 0f64,
+            reliability: 1f64,
+            strategy: ParticipantStrategy::default(),
         }
     }
 
+    fn set_strategy(&mut self, kind: StrategyKind, params: &Params) {
+        self.strategy =
+            ParticipantStrategy::new(kind, params.bandit_learning_rate, params.bandit_exploration);
+    }
+
+    // Locks `amount` of the stake for `unstake_delay` epochs.
+    fn unstake<T: EventConsumer>(
+        &mut self,
+        amount: f64,
+        to_liquid: bool,
+        params: &Params,
+        time: usize,
+        events: &mut T,
+    ) -> Result<(), SimError> {
+        self.num_tokens -= amount;
+        self.unstaking.push(Unstaking {
+            epochs_left: params.unstake_delay,
+            amount,
+            to_liquid,
+        });
+        events.push(Event {
+            time,
+            info: event::Info::UnstakeRequested {
+                participant_id: self.id,
+                amount,
+            },
+        })?;
+        events.push(Event {
+            time,
+            info: event::Info::StakeChange {
+                participant_id: self.id,
+                change_amount: -amount,
+            },
+        })
+    }
+
+    // Moves `fraction` of the tokens to the liquid balance.
+    fn keep_liquid(&mut self, fraction: f64) {
+        let amount = self.num_tokens * fraction;
+        self.num_tokens -= amount;
+        self.liquid_tokens += amount;
+    }
+
     fn split(self, id_generator: &mut IdGenerator) -> (Self, Self) {
         let new_id_1 = id_generator.next();
         let new_id_2 = id_generator.next();
@@ -118,8 +436,20 @@ impl Participant {
             id: new_id_1,
             num_tokens: self.num_tokens / 2.0,
             role: self.role,
+            shard: self.shard,
+            liquid_tokens: self.liquid_tokens / 2.0,
+            unstaking: self
+                .unstaking
+                .iter()
+                .map(|u| Unstaking {
+                    amount: u.amount / 2.0,
+                    ..*u
+                })
+                .collect(),
             most_recent_stake_change: self.most_recent_stake_change / 2.0,
             expected_stake_change_on_switch: self.expected_stake_change_on_switch / 2.0,
+            reliability: self.reliability,
+            strategy: self.strategy,
         };
         let p1 = template.clone();
         template.id = new_id_2;
@@ -128,18 +458,28 @@ impl Participant {
     }
 }
 
-fn update_token_amounts<T: EventConsumer, S: BuildHasher>(
-    participants: &mut HashMap<Id, Participant, S>,
+// Pays the rewards and costs of the epoch ending at `time`. Returns the producers which
+// were kicked out for producing too little, and the rewards paid out (less than the total
+// reward if producers missed blocks or chunks, or a shard had no COPs). The kicked out
+// producers have already lost their role, and their delegators have been redelegated like
+// those of bankrupt participants. Participants whose stake runs out only go bankrupt if
+// they have no liquid or unstaking tokens left; otherwise they just lose their role.
+fn update_token_amounts<T: EventConsumer, R: Rng>(
+    participants: &mut ParticipantMap,
     params: &Params,
     time: usize,
     events: &mut T,
-) {
+    rng: &mut R,
+) -> Result<(Vec<Id>, f64), SimError> {
     // effective_stake = num_tokens (owned) + delegated tokens
-    let (effective_stakes, delegated_roles, total_bp_stake, total_cop_stake) = {
+    let shard_of = |p: &Participant| p.shard.min(params.num_shards - 1);
+    let (effective_stakes, delegated_roles, delegated_shards, total_bp_stake, cop_stakes) = {
         let mut effective_stakes: HashMap<Id, f64> = HashMap::new();
         let mut delegated_roles: HashMap<Id, Option<Role>> = HashMap::new();
+        let mut delegated_shards: HashMap<Id, usize> = HashMap::new();
         let mut total_bp_stake = 0f64;
-        let mut total_cop_stake = 0f64;
+        // COP stake of every shard
+        let mut cop_stakes = vec![0f64; params.num_shards];
         for p in participants.values() {
             let stake = effective_stakes.entry(p.id).or_insert(0f64);
             *stake += p.num_tokens;
@@ -148,10 +488,17 @@ fn update_token_amounts<T: EventConsumer, S: BuildHasher>(
                     total_bp_stake += p.num_tokens;
                 }
                 Some(Role::ChunkOnlyProducer) => {
-                    total_cop_stake += p.num_tokens;
+                    cop_stakes[shard_of(p)] += p.num_tokens;
                 }
                 Some(Role::Delegator(delegatee_id)) => {
-                    let delegatee = participants.get(&delegatee_id).unwrap();
+                    let delegatee =
+                        participants
+                            .get(&delegatee_id)
+                            .ok_or(SimError::MissingDelegatee {
+                                time,
+                                participant_id: p.id,
+                                delegatee_id,
+                            })?;
                     match &delegatee.role {
                         Some(Role::BlockProducer) => {
                             *effective_stakes.entry(delegatee_id).or_insert(0f64) += p.num_tokens;
@@ -160,8 +507,9 @@ fn update_token_amounts<T: EventConsumer, S: BuildHasher>(
                         }
                         Some(Role::ChunkOnlyProducer) => {
                             *effective_stakes.entry(delegatee_id).or_insert(0f64) += p.num_tokens;
-                            total_cop_stake += p.num_tokens;
+                            cop_stakes[shard_of(delegatee)] += p.num_tokens;
                             delegated_roles.insert(p.id, Some(Role::ChunkOnlyProducer));
+                            delegated_shards.insert(p.id, shard_of(delegatee));
                         }
                         None | Some(Role::Delegator(_)) => (),
                     }
@@ -172,40 +520,111 @@ fn update_token_amounts<T: EventConsumer, S: BuildHasher>(
         (
             effective_stakes,
             delegated_roles,
+            delegated_shards,
             total_bp_stake,
-            total_cop_stake,
+            cop_stakes,
         )
     };
 
-    let bp_cost = params.chunk_only_producer_cost * params.block_producer_cost_factor;
+    // Every producer is assigned one block (chunk) per time step and produces it with
+    // probability `reliability`. Rewards are in proportion to what was produced.
+    let mut reward_factors: HashMap<Id, f64> = HashMap::new();
+    let mut kicked_out: Vec<Id> = Vec::new();
+    for p in participants.values() {
+        let is_producer = matches!(
+            p.role,
+            Some(Role::BlockProducer) | Some(Role::ChunkOnlyProducer)
+        );
+        if !is_producer || p.reliability >= 1.0 {
+            continue;
+        }
+        let num_assigned = params.epoch_length as u64;
+        let num_produced = Binomial::new(num_assigned, p.reliability)
+            .expect("reliability is a probability")
+            .sample(rng);
+        if num_produced == num_assigned {
+            continue;
+        }
+        events.push(Event {
+            time,
+            info: event::Info::ProductionMissed {
+                participant_id: p.id,
+                num_assigned,
+                num_missed: num_assigned - num_produced,
+            },
+        })?;
+        let uptime = num_produced as f64 / num_assigned as f64;
+        if uptime < params.online_threshold {
+            events.push(Event {
+                time,
+                info: event::Info::KickedOut {
+                    participant_id: p.id,
+                },
+            })?;
+            kicked_out.push(p.id);
+            reward_factors.insert(p.id, 0.0);
+        } else {
+            reward_factors.insert(p.id, uptime);
+        }
+    }
+    let reward_factor = |id: &Id| reward_factors.get(id).copied().unwrap_or(1.0);
+
+    // rewards and costs are per time step but paid for the whole epoch
+    let epoch_length = params.epoch_length as f64;
+    let total_reward = params.total_reward * epoch_length;
+    let cop_cost = params.chunk_only_producer_cost * epoch_length;
+    let bp_cost = cop_cost * params.block_producer_cost_factor;
     let cop_reward_fraction = 1f64 - params.block_producer_reward_fraction;
     let bp_delegator_cost = 1f64 - params.block_producer_delegation_fee;
     let cop_delegator_cost = 1f64 - params.chunk_only_producer_delegation_fee;
+    // COP rewards are split between the shards in proportion to their weight, and within
+    // a shard in proportion to stake. COP costs also scale with the weight of the shard.
+    let shard_weights = params.shard_weights();
+    let shard_rewards: Vec<f64> = shard_weights
+        .iter()
+        .map(|w| total_reward * w / (params.num_shards as f64))
+        .collect();
+    let shard_cop_costs: Vec<f64> = shard_weights.iter().map(|w| cop_cost * w).collect();
+    // a participant switching to COP would join the shard with the least stake
+    let mut lightest = 0;
+    for (shard, stake) in cop_stakes.iter().enumerate() {
+        if *stake < cop_stakes[lightest] {
+            lightest = shard;
+        }
+    }
     let mut bankrupt_participants: Vec<Id> = Vec::new();
+    let mut out_of_stake: Vec<Id> = Vec::new();
+    let mut distributed = 0f64;
     for p in participants.values_mut() {
         let change = match &p.role {
-            None => 0f64, // bystanders gain nothing and lose nothing
+            // only the liquid balance earns the external yield, see `pay_external_yield`
+            None => 0f64,
             Some(Role::BlockProducer) => {
-                let effective_stake = effective_stakes.get(&p.id).unwrap();
+                let effective_stake = &effective_stakes[&p.id];
                 let delegated_stake = effective_stake - p.num_tokens;
-                let bp_profit =
-                    (params.total_reward * params.block_producer_reward_fraction * effective_stake
+                // includes what the delegators get
+                distributed += reward_factor(&p.id)
+                    * total_reward
+                    * params.block_producer_reward_fraction
+                    * effective_stake
+                    / total_bp_stake;
+                let bp_profit = reward_factor(&p.id)
+                    * ((total_reward * params.block_producer_reward_fraction * effective_stake
                         / total_bp_stake)
-                        - (params.total_reward
+                        - (total_reward
                             * params.block_producer_reward_fraction
                             * bp_delegator_cost
                             * delegated_stake
-                            / total_bp_stake)
-                        - bp_cost;
+                            / total_bp_stake))
+                    - bp_cost;
                 // profit under the assumption only this participant switches from BP to COP
-                let cop_profit = (params.total_reward * cop_reward_fraction * effective_stake
-                    / (effective_stake + total_cop_stake))
-                    - (params.total_reward
-                        * cop_reward_fraction
-                        * cop_delegator_cost
-                        * delegated_stake
-                        / (effective_stake + total_cop_stake))
-                    - params.chunk_only_producer_cost;
+                let shard_reward = shard_rewards[lightest];
+                let shard_stake = cop_stakes[lightest];
+                let cop_profit = (shard_reward * cop_reward_fraction * effective_stake
+                    / (effective_stake + shard_stake))
+                    - (shard_reward * cop_reward_fraction * cop_delegator_cost * delegated_stake
+                        / (effective_stake + shard_stake))
+                    - shard_cop_costs[lightest];
 
                 p.num_tokens += bp_profit;
                 p.most_recent_stake_change = bp_profit;
@@ -214,21 +633,28 @@ fn update_token_amounts<T: EventConsumer, S: BuildHasher>(
                 bp_profit
             }
             Some(Role::ChunkOnlyProducer) => {
-                let effective_stake = effective_stakes.get(&p.id).unwrap();
+                let effective_stake = &effective_stakes[&p.id];
                 let delegated_stake = effective_stake - p.num_tokens;
-                let cop_profit = (params.total_reward * cop_reward_fraction * effective_stake
-                    / total_cop_stake)
-                    - (params.total_reward
-                        * cop_reward_fraction
-                        * cop_delegator_cost
-                        * delegated_stake
-                        / total_cop_stake)
-                    - params.chunk_only_producer_cost;
+                let shard = shard_of(p);
+                distributed += reward_factor(&p.id)
+                    * shard_rewards[shard]
+                    * cop_reward_fraction
+                    * effective_stake
+                    / cop_stakes[shard];
+                let cop_profit = reward_factor(&p.id)
+                    * ((shard_rewards[shard] * cop_reward_fraction * effective_stake
+                        / cop_stakes[shard])
+                        - (shard_rewards[shard]
+                            * cop_reward_fraction
+                            * cop_delegator_cost
+                            * delegated_stake
+                            / cop_stakes[shard]))
+                    - shard_cop_costs[shard];
 
                 let bp_profit =
-                    (params.total_reward * params.block_producer_reward_fraction * effective_stake
+                    (total_reward * params.block_producer_reward_fraction * effective_stake
                         / (effective_stake + total_bp_stake))
-                        - (params.total_reward
+                        - (total_reward
                             * params.block_producer_reward_fraction
                             * bp_delegator_cost
                             * delegated_stake
@@ -241,47 +667,63 @@ fn update_token_amounts<T: EventConsumer, S: BuildHasher>(
 
                 cop_profit
             }
-            Some(Role::Delegator(_)) => match delegated_roles.get(&p.id).unwrap() {
-                Some(Role::BlockProducer) => {
-                    let bp_reward =
-                        params.total_reward * params.block_producer_reward_fraction * p.num_tokens
+            // delegators whose delegatee is not a BP or COP gain nothing
+            Some(Role::Delegator(delegatee_id)) => {
+                match delegated_roles.get(&p.id).copied().flatten() {
+                    Some(Role::BlockProducer) => {
+                        let bp_reward = reward_factor(delegatee_id)
+                            * total_reward
+                            * params.block_producer_reward_fraction
+                            * p.num_tokens
                             / total_bp_stake;
-                    let bp_fee = bp_reward * params.block_producer_delegation_fee;
-                    let bp_stake_change = bp_reward - bp_fee;
+                        let bp_fee = bp_reward * params.block_producer_delegation_fee;
+                        let bp_stake_change = bp_reward - bp_fee;
 
-                    let cop_reward = params.total_reward * cop_reward_fraction * p.num_tokens
-                        / (p.num_tokens + total_cop_stake);
-                    let cop_fee = cop_reward * params.chunk_only_producer_delegation_fee;
-                    let cop_stake_change = cop_reward - cop_fee;
+                        let cop_reward =
+                            shard_rewards[lightest] * cop_reward_fraction * p.num_tokens
+                                / (p.num_tokens + cop_stakes[lightest]);
+                        let cop_fee = cop_reward * params.chunk_only_producer_delegation_fee;
+                        let cop_stake_change = cop_reward - cop_fee;
 
-                    p.num_tokens += bp_stake_change;
-                    p.most_recent_stake_change = bp_stake_change;
-                    p.expected_stake_change_on_switch = cop_stake_change;
+                        p.num_tokens += bp_stake_change;
+                        p.most_recent_stake_change = bp_stake_change;
+                        p.expected_stake_change_on_switch = cop_stake_change;
 
-                    bp_stake_change
-                }
-                Some(Role::ChunkOnlyProducer) => {
-                    let cop_reward =
-                        params.total_reward * cop_reward_fraction * p.num_tokens / total_cop_stake;
-                    let cop_fee = cop_reward * params.chunk_only_producer_delegation_fee;
-                    let cop_stake_change = cop_reward - cop_fee;
-
-                    let bp_reward =
-                        params.total_reward * params.block_producer_reward_fraction * p.num_tokens
-                            / (p.num_tokens + total_bp_stake);
-                    let bp_fee = bp_reward * params.block_producer_delegation_fee;
-                    let bp_stake_change = bp_reward - bp_fee;
-
-                    p.num_tokens += cop_stake_change;
-                    p.most_recent_stake_change = cop_stake_change;
-                    p.expected_stake_change_on_switch = bp_stake_change;
-
-                    cop_stake_change
+                        bp_stake_change
+                    }
+                    Some(Role::ChunkOnlyProducer) => {
+                        let shard = delegated_shards[&p.id];
+                        let cop_reward = reward_factor(delegatee_id)
+                            * shard_rewards[shard]
+                            * cop_reward_fraction
+                            * p.num_tokens
+                            / cop_stakes[shard];
+                        let cop_fee = cop_reward * params.chunk_only_producer_delegation_fee;
+                        let cop_stake_change = cop_reward - cop_fee;
+
+                        let bp_reward =
+                            total_reward * params.block_producer_reward_fraction * p.num_tokens
+                                / (p.num_tokens + total_bp_stake);
+                        let bp_fee = bp_reward * params.block_producer_delegation_fee;
+                        let bp_stake_change = bp_reward - bp_fee;
+
+                        p.num_tokens += cop_stake_change;
+                        p.most_recent_stake_change = cop_stake_change;
+                        p.expected_stake_change_on_switch = bp_stake_change;
+
+                        cop_stake_change
+                    }
+                    None | Some(Role::Delegator(_)) => 0f64,
                 }
-                None | Some(Role::Delegator(_)) => 0f64,
-            },
+            }
         };
 
+        if !change.is_finite() {
+            return Err(SimError::NonFiniteStakeChange {
+                time,
+                participant_id: p.id,
+            });
+        }
         if change != 0f64 {
             if change > 0f64 || (change < 0f64 && p.num_tokens > 0f64) {
                 events.push(Event {
@@ -290,31 +732,283 @@ fn update_token_amounts<T: EventConsumer, S: BuildHasher>(
                         participant_id: p.id,
                         change_amount: change,
                     },
-                })
+                })?;
+            } else if p.liquid_tokens > 0f64 || !p.unstaking.is_empty() {
+                // the loss beyond its stake is not taken from its other tokens
+                let change_amount = change - p.num_tokens;
+                p.num_tokens = 0f64;
+                if change_amount != 0f64 {
+                    events.push(Event {
+                        time,
+                        info: event::Info::StakeChange {
+                            participant_id: p.id,
+                            change_amount,
+                        },
+                    })?;
+                }
+                p.role = None;
+                events.push(Event {
+                    time,
+                    info: event::Info::RoleChange {
+                        participant_id: p.id,
+                        new_role: None,
+                    },
+                })?;
+                out_of_stake.push(p.id);
             } else {
                 events.push(Event {
                     time,
                     info: event::Info::ParticipantBankrupt {
                         participant_id: p.id,
                     },
-                });
+                })?;
                 bankrupt_participants.push(p.id);
             }
         }
     }
 
-    for id in bankrupt_participants {
-        participants.remove(&id);
+    for id in bankrupt_participants.iter() {
+        participants.swap_remove(id);
+    }
+    // the delegations to kicked out producers, and to those whose stake ran out, dangle
+    // as well
+    let mut removed = bankrupt_participants;
+    removed.extend(out_of_stake);
+    for id in kicked_out.iter() {
+        if let Some(p) = participants.get_mut(id).filter(|p| p.role.is_some()) {
+            p.role = None;
+            events.push(Event {
+                time,
+                info: event::Info::RoleChange {
+                    participant_id: p.id,
+                    new_role: None,
+                },
+            })?;
+            removed.push(p.id);
+        }
+    }
+    redelegate(participants, params, time, events, &removed, &[])?;
+    Ok((kicked_out, distributed))
+}
+
+// Pays out the unstaked tokens whose delay ends with the epoch ending at `time`.
+fn withdraw_unstaked<T: EventConsumer>(
+    participants: &mut ParticipantMap,
+    time: usize,
+    events: &mut T,
+) -> Result<(), SimError> {
+    for p in participants.values_mut() {
+        if p.unstaking.is_empty() {
+            continue;
+        }
+        for u in p.unstaking.iter_mut() {
+            u.epochs_left -= 1;
+        }
+        let (withdrawn, unstaking) = p.unstaking.iter().partition(|u| u.epochs_left == 0);
+        p.unstaking = unstaking;
+        for u in withdrawn {
+            let change_amount = u.amount;
+            events.push(Event {
+                time,
+                info: event::Info::Withdrawn {
+                    participant_id: p.id,
+                    amount: change_amount,
+                },
+            })?;
+            let info = if u.to_liquid {
+                p.liquid_tokens += change_amount;
+                event::Info::LiquidChange {
+                    participant_id: p.id,
+                    change_amount,
+                }
+            } else {
+                p.num_tokens += change_amount;
+                event::Info::StakeChange {
+                    participant_id: p.id,
+                    change_amount,
+                }
+            };
+            events.push(Event { time, info })?;
+        }
+    }
+    Ok(())
+}
+
+// Updates the liquid balances and the unstaked tokens at the end of the epoch ending at
+// `time`. The tokens withdrawn now were locked during the epoch, so they earn no yield
+// for it, and they are withdrawn before any new unstake requests so that those are locked
+// for the full `unstake_delay`.
+fn settle_balances<T: EventConsumer>(
+    participants: &mut ParticipantMap,
+    params: &Params,
+    time: usize,
+    events: &mut T,
+) -> Result<(), SimError> {
+    pay_external_yield(participants, params, time, events)?;
+    withdraw_unstaked(participants, time, events)?;
+    update_liquid_balances(participants, params, time, events)
+}
+
+// Pays the external yield of the epoch ending at `time` on the liquid balances.
+fn pay_external_yield<T: EventConsumer>(
+    participants: &mut ParticipantMap,
+    params: &Params,
+    time: usize,
+    events: &mut T,
+) -> Result<(), SimError> {
+    let epoch_length = params.epoch_length as f64;
+    for p in participants.values_mut() {
+        let liquid_yield = params.external_yield * epoch_length * p.liquid_tokens;
+        if liquid_yield > 0.0 {
+            p.liquid_tokens += liquid_yield;
+            events.push(Event {
+                time,
+                info: event::Info::LiquidChange {
+                    participant_id: p.id,
+                    change_amount: liquid_yield,
+                },
+            })?;
+        }
+    }
+    Ok(())
+}
+
+// Moves tokens between stake and liquid balance towards whichever paid more per token in
+// the epoch ending at `time`. The tokens of participants without a role earn nothing, so
+// they move to the liquid balance whenever the external yield is positive; since they are
+// not staked, they move there without unstaking.
+fn update_liquid_balances<T: EventConsumer>(
+    participants: &mut ParticipantMap,
+    params: &Params,
+    time: usize,
+    events: &mut T,
+) -> Result<(), SimError> {
+    if params.rebalance_rate <= 0.0 {
+        return Ok(());
+    }
+    let epoch_length = params.epoch_length as f64;
+    for p in participants.values_mut() {
+        let staking_yield = if p.role.is_some() && p.num_tokens > 0.0 {
+            p.most_recent_stake_change / (p.num_tokens * epoch_length)
+        } else {
+            0.0
+        };
+        let stake_change = if staking_yield < params.external_yield {
+            -params.rebalance_rate * p.num_tokens
+        } else {
+            params.rebalance_rate * p.liquid_tokens
+        };
+        if stake_change < 0.0 && params.unstake_delay > 0 && p.role.is_some() {
+            p.unstake(-stake_change, true, params, time, events)?;
+        } else if stake_change != 0.0 {
+            p.num_tokens += stake_change;
+            p.liquid_tokens -= stake_change;
+            events.push(Event {
+                time,
+                info: event::Info::StakeChange {
+                    participant_id: p.id,
+                    change_amount: stake_change,
+                },
+            })?;
+            events.push(Event {
+                time,
+                info: event::Info::LiquidChange {
+                    participant_id: p.id,
+                    change_amount: -stake_change,
+                },
+            })?;
+        }
+    }
+    Ok(())
+}
+
+// Slashes the producers which misbehaved in the epoch ending at `time`, then pays the
+// rewards and costs of the epoch. Slashing comes first, while the producers which are
+// kicked out for the epoch still have their role and delegators. Returns the kicked out
+// producers, the rewards paid out and the tokens burned by slashing.
+fn settle_rewards<T: EventConsumer, R: Rng>(
+    participants: &mut ParticipantMap,
+    params: &Params,
+    time: usize,
+    events: &mut T,
+    rng: &mut R,
+) -> Result<(Vec<Id>, f64, f64), SimError> {
+    let slashed = slash(participants, params, time, events, rng)?;
+    let (kicked_out, distributed) = update_token_amounts(participants, params, time, events, rng)?;
+    Ok((kicked_out, distributed, slashed))
+}
+
+// Burns `slash_fraction` of the tokens of every producer which misbehaved in the epoch
+// ending at `time`, and of the tokens delegated to it. Returns the number of tokens burned.
+fn slash<T: EventConsumer, R: Rng>(
+    participants: &mut ParticipantMap,
+    params: &Params,
+    time: usize,
+    events: &mut T,
+    rng: &mut R,
+) -> Result<f64, SimError> {
+    let epoch_start = time.saturating_sub(params.epoch_length);
+    let mut offenders: Vec<Id> = Vec::new();
+    for p in participants.values() {
+        let probability = match p.role {
+            Some(Role::BlockProducer) => params.block_producer_misbehaviour_probability,
+            Some(Role::ChunkOnlyProducer) => params.chunk_only_producer_misbehaviour_probability,
+            None | Some(Role::Delegator(_)) => continue,
+        };
+        let scripted = params
+            .scripted_misbehaviour
+            .iter()
+            .any(|m| m.participant_id == p.id && epoch_start < m.time && m.time <= time);
+        // only use the RNG if misbehaviour is possible, so runs without it are unchanged
+        if scripted || (probability > 0.0 && rng.gen::<f64>() < probability) {
+            offenders.push(p.id);
+        }
+    }
+
+    let mut total_amount = 0f64;
+    for id in offenders {
+        let mut delegator_amounts = Vec::new();
+        for p in participants.values_mut() {
+            if p.role == Some(Role::Delegator(id)) {
+                let amount = p.num_tokens * params.slash_fraction;
+                p.num_tokens -= amount;
+                total_amount += amount;
+                delegator_amounts.push((p.id, amount));
+            }
+        }
+        let p = participants
+            .get_mut(&id)
+            .ok_or(SimError::MissingParticipant {
+                time,
+                participant_id: id,
+            })?;
+        let amount = p.num_tokens * params.slash_fraction;
+        p.num_tokens -= amount;
+        total_amount += amount;
+        events.push(Event {
+            time,
+            info: event::Info::Slashed {
+                participant_id: id,
+                amount,
+                delegator_amounts,
+            },
+        })?;
     }
+    Ok(total_amount)
 }
 
-fn manage_participants<T: EventConsumer, R: Rng, S: BuildHasher>(
-    participants: &mut HashMap<Id, Participant, S>,
+fn manage_participants<T: EventConsumer, R: Rng>(
+    participants: &mut ParticipantMap,
+    params: &Params,
     time: usize,
     events: &mut T,
     id_generator: &mut IdGenerator,
     rng: &mut R,
-) {
+) -> Result<(), SimError> {
+    let missing = |participant_id| SimError::MissingParticipant {
+        time,
+        participant_id,
+    };
     // either introduce a new participant, split one participant into two, or merge two participants
     let x: f64 = if participants.is_empty() {
         0.0
@@ -328,29 +1022,46 @@ fn manage_participants<T: EventConsumer, R: Rng, S: BuildHasher>(
             100.0
         } else {
             let idx = rng.gen_range(0..participants.len());
-            participants.values().skip(idx).next().unwrap().num_tokens
+            participants[idx].num_tokens
         };
         let modifier: f64 = 2.0 * rng.gen::<f64>();
-        let p = Participant {
+        let mut p = Participant {
             id: new_id,
             num_tokens: modifier * base_stake,
             role: None,
+            shard: 0,
+            liquid_tokens: 0f64,
+            unstaking: Vec::new(),
             most_recent_stake_change: 0f64,
             expected_stake_change_on_switch: 0f64,
+            reliability: params.draw_reliability(rng),
+            strategy: ParticipantStrategy::default(),
         };
+        p.set_strategy(params.draw_strategy(rng), params);
+        p.keep_liquid(params.liquid_fraction);
         events.push(Event {
             time,
             info: event::Info::ParticipantCreated {
                 participant_id: new_id,
                 num_tokens: p.num_tokens,
+                strategy: p.strategy.kind(),
             },
-        });
+        })?;
+        if p.liquid_tokens > 0.0 {
+            events.push(Event {
+                time,
+                info: event::Info::LiquidChange {
+                    participant_id: new_id,
+                    change_amount: p.liquid_tokens,
+                },
+            })?;
+        }
         participants.insert(new_id, p);
     } else if x < 0.667 {
         // split one participant into two
         let idx = rng.gen_range(0..participants.len());
-        let id = participants.values().skip(idx).next().unwrap().id;
-        let original_particpiant = participants.remove(&id).unwrap();
+        let id = participants[idx].id;
+        let original_particpiant = participants.swap_remove(&id).ok_or_else(|| missing(id))?;
         let (p1, p2) = original_particpiant.split(id_generator);
         events.push(Event {
             time,
@@ -358,29 +1069,48 @@ fn manage_participants<T: EventConsumer, R: Rng, S: BuildHasher>(
                 participant_id: id,
                 new_participant_ids: (p1.id, p2.id),
             },
-        });
+        })?;
+        let successors = [p1.id, p2.id];
         participants.insert(p1.id, p1);
         participants.insert(p2.id, p2);
+        redelegate(participants, params, time, events, &[id], &successors)?;
     } else {
         // merge two participants
         let idx = rng.gen_range(0..participants.len());
-        let id = participants.values().skip(idx).next().unwrap().id;
-        let p1 = participants.remove(&id).unwrap();
+        let id = participants[idx].id;
+        let p1 = participants.swap_remove(&id).ok_or_else(|| missing(id))?;
         if let Some(p2_id) = participants
             .values()
-            .filter(|p| p.role == p1.role)
-            .next()
+            .find(|p| p.role == p1.role)
             .map(|p| p.id)
         {
-            let p2 = participants.remove(&p2_id).unwrap();
+            let p2 = participants
+                .swap_remove(&p2_id)
+                .ok_or_else(|| missing(p2_id))?;
             let new_id = id_generator.next();
             let p = Participant {
                 id: new_id,
                 num_tokens: p1.num_tokens + p2.num_tokens,
                 role: p1.role,
+                shard: p1.shard,
+                liquid_tokens: p1.liquid_tokens + p2.liquid_tokens,
+                unstaking: p1
+                    .unstaking
+                    .iter()
+                    .chain(p2.unstaking.iter())
+                    .copied()
+                    .collect(),
                 most_recent_stake_change: p1.most_recent_stake_change + p2.most_recent_stake_change,
                 expected_stake_change_on_switch: p1.expected_stake_change_on_switch
                     + p2.expected_stake_change_on_switch,
+                // weighted by stake, unless neither has any (all of it is unstaking)
+                reliability: if p1.num_tokens + p2.num_tokens > 0.0 {
+                    (p1.reliability * p1.num_tokens + p2.reliability * p2.num_tokens)
+                        / (p1.num_tokens + p2.num_tokens)
+                } else {
+                    p1.reliability
+                },
+                strategy: p1.strategy.clone(),
             };
             events.push(Event {
                 time,
@@ -388,142 +1118,479 @@ fn manage_participants<T: EventConsumer, R: Rng, S: BuildHasher>(
                     participant_ids: (p1.id, p2.id),
                     new_participant_id: new_id,
                 },
-            });
+            })?;
             participants.insert(new_id, p);
+            redelegate(
+                participants,
+                params,
+                time,
+                events,
+                &[p1.id, p2.id],
+                &[new_id],
+            )?;
         }
     }
+    Ok(())
 }
 
-fn update_roles<T: EventConsumer, R: Rng, S: BuildHasher>(
-    participants: &mut HashMap<Id, Participant, S>,
+// Applies `params.dangling_delegation_policy` to the delegators of the `removed`
+// participants. `successors` are the participants which took over their tokens
+// (empty if they went bankrupt).
+fn redelegate<T: EventConsumer>(
+    participants: &mut ParticipantMap,
     params: &Params,
     time: usize,
     events: &mut T,
-    rng: &mut R,
-) {
-    let mut bp_proposals = Vec::with_capacity(params.num_block_producers);
-    let mut cop_proposals = Vec::with_capacity(params.num_chunk_only_producers);
+    removed: &[Id],
+    successors: &[Id],
+) -> Result<(), SimError> {
+    let mut dangling: Vec<Id> = participants
+        .values()
+        .filter(|p| match p.role {
+            Some(Role::Delegator(id)) => removed.contains(&id),
+            _ => false,
+        })
+        .map(|p| p.id)
+        .collect();
+    if dangling.is_empty() {
+        return Ok(());
+    }
+    dangling.sort_unstable();
 
-    for p in participants.values() {
-        // 5% chance to switch roles if the grass is greener on the other side, 1% otherwise
-        let probability_to_switch =
-            if p.most_recent_stake_change > p.expected_stake_change_on_switch {
-                0.01f64
-            } else {
-                0.05f64
-            };
-        let x: f64 = rng.gen();
-        match &p.role {
-            None => {
-                // Did not have a role in the last round; Randomly become BP or COP
-                if rng.gen() {
-                    bp_proposals.push((p.num_tokens, p.id));
-                } else {
-                    cop_proposals.push((p.num_tokens, p.id));
-                }
-            }
-            Some(Role::BlockProducer) => {
-                if x < probability_to_switch {
-                    cop_proposals.push((p.num_tokens, p.id));
-                } else {
-                    bp_proposals.push((p.num_tokens, p.id));
-                }
-            }
-            Some(Role::ChunkOnlyProducer) => {
-                if x < probability_to_switch {
-                    bp_proposals.push((p.num_tokens, p.id));
-                } else {
-                    cop_proposals.push((p.num_tokens, p.id));
-                }
+    // a successor can only be delegated to if it is a producer
+    let successors: Vec<Id> = successors
+        .iter()
+        .copied()
+        .filter(|id| {
+            participants.get(id).map_or(false, |p| {
+                matches!(
+                    p.role,
+                    Some(Role::BlockProducer) | Some(Role::ChunkOnlyProducer)
+                )
+            })
+        })
+        .collect();
+    let best = match params.dangling_delegation_policy {
+        DanglingDelegationPolicy::BestProducer => market(participants, params).best_producer(),
+        _ => None,
+    };
+
+    for (i, id) in dangling.iter().enumerate() {
+        let new_role = match params.dangling_delegation_policy {
+            DanglingDelegationPolicy::Undelegate => None,
+            DanglingDelegationPolicy::FollowSuccessor if successors.is_empty() => None,
+            DanglingDelegationPolicy::FollowSuccessor => {
+                Some(Role::Delegator(successors[i % successors.len()]))
             }
-            Some(Role::Delegator(id)) => match participants.get(id).and_then(|d| d.role) {
-                Some(Role::BlockProducer) => {
-                    if x < probability_to_switch {
-                        cop_proposals.push((p.num_tokens, p.id));
-                    } else {
-                        bp_proposals.push((p.num_tokens, p.id));
-                    }
-                }
-                Some(Role::ChunkOnlyProducer) => {
-                    if x < probability_to_switch {
-                        bp_proposals.push((p.num_tokens, p.id));
-                    } else {
-                        cop_proposals.push((p.num_tokens, p.id));
-                    }
-                }
-                None | Some(Role::Delegator(_)) => {
-                    if rng.gen() {
-                        bp_proposals.push((p.num_tokens, p.id));
-                    } else {
-                        cop_proposals.push((p.num_tokens, p.id));
-                    }
-                }
+            DanglingDelegationPolicy::BestProducer => best.map(Role::Delegator),
+        };
+        let p = participants
+            .get_mut(id)
+            .ok_or(SimError::MissingParticipant {
+                time,
+                participant_id: *id,
+            })?;
+        p.role = new_role;
+        events.push(Event {
+            time,
+            info: event::Info::RoleChange {
+                participant_id: p.id,
+                new_role,
             },
+        })?;
+    }
+    Ok(())
+}
+
+// Tokens staked with a BP or a COP, including delegated ones.
+fn staked_tokens(participants: &ParticipantMap) -> f64 {
+    participants
+        .values()
+        .filter(|p| {
+            let role = match p.role {
+                Some(Role::Delegator(id)) => participants.get(&id).and_then(|d| d.role),
+                role => role,
+            };
+            matches!(
+                role,
+                Some(Role::BlockProducer) | Some(Role::ChunkOnlyProducer)
+            )
+        })
+        .fold(0f64, |total, p| total + p.num_tokens)
+}
+
+// Rates paid to delegators and the largest producers, as seen by the strategies.
+fn market(participants: &ParticipantMap, params: &Params) -> Market {
+    let mut total_bp_stake = 0f64;
+    let mut total_cop_stake = 0f64;
+    for p in participants.values() {
+        let role = match p.role {
+            Some(Role::Delegator(id)) => participants.get(&id).and_then(|d| d.role),
+            role => role,
+        };
+        match role {
+            Some(Role::BlockProducer) => total_bp_stake += p.num_tokens,
+            Some(Role::ChunkOnlyProducer) => total_cop_stake += p.num_tokens,
+            None | Some(Role::Delegator(_)) => (),
         }
     }
 
-    bp_proposals.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap().reverse());
-    cop_proposals.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap().reverse());
+    let largest = |role: Role| {
+        participants
+            .values()
+            .filter(|p| p.role == Some(role))
+            .max_by(|a, b| {
+                a.num_tokens
+                    .partial_cmp(&b.num_tokens)
+                    .unwrap()
+                    .then(b.id.cmp(&a.id))
+            })
+            .map(|p| p.id)
+    };
+    // there is no rate for a role without stake
+    let rate = |reward: f64, stake: f64| {
+        if stake > 0.0 {
+            Some(reward / stake)
+        } else {
+            None
+        }
+    };
+    Market {
+        block_producer_delegator_rate: rate(
+            params.total_reward
+                * params.block_producer_reward_fraction
+                * (1f64 - params.block_producer_delegation_fee),
+            total_bp_stake,
+        ),
+        chunk_only_producer_delegator_rate: rate(
+            params.total_reward
+                * (1f64 - params.block_producer_reward_fraction)
+                * (1f64 - params.chunk_only_producer_delegation_fee),
+            total_cop_stake,
+        ),
+        largest_block_producer: largest(Role::BlockProducer),
+        largest_chunk_only_producer: largest(Role::ChunkOnlyProducer),
+        external_yield: params.external_yield,
+    }
+}
 
-    let mut assign_role = |p: &mut Participant, new_role: Option<Role>| {
-        if p.role != new_role {
-            p.role = new_role;
+// Every participant proposes a role according to its strategy and the proposals to become
+// a BP or a COP are selected.
+// Returns the new roles of the participants, which take effect later.
+fn select_roles<T: EventConsumer, R: Rng>(
+    participants: &mut ParticipantMap,
+    params: &Params,
+    time: usize,
+    events: &mut T,
+    rng: &mut R,
+) -> Result<Vec<SelectedRole>, SimError> {
+    let mut roles = Vec::with_capacity(participants.len());
+    let mut bp_proposals = Vec::with_capacity(params.num_block_producers);
+    let mut cop_proposals = Vec::with_capacity(params.num_chunk_only_producers);
+
+    let market = market(participants, params);
+    let observations: Vec<Observation> = participants
+        .values()
+        .map(|p| Observation {
+            id: p.id,
+            num_tokens: p.num_tokens,
+            role: p.role,
+            delegatee_role: match p.role {
+                Some(Role::Delegator(id)) => participants.get(&id).and_then(|d| d.role),
+                _ => None,
+            },
+            most_recent_stake_change: p.most_recent_stake_change,
+            expected_stake_change_on_switch: p.expected_stake_change_on_switch,
+        })
+        .collect();
+    for (p, observation) in participants.values_mut().zip(observations) {
+        // all of its tokens are locked, so there is nothing to stake
+        if p.num_tokens <= 0.0 {
+            roles.push(SelectedRole::new(p.id, None, false));
+            continue;
+        }
+        let proposal = p.strategy.propose(&observation, &market, rng);
+        if let ParticipantStrategy::Bandit(bandit) = &p.strategy {
             events.push(Event {
                 time,
-                info: event::Info::RoleChange {
+                info: event::Info::ValueEstimates {
                     participant_id: p.id,
-                    new_role,
+                    values: bandit.values(),
                 },
-            });
+            })?;
+        }
+        match proposal {
+            Proposal::BlockProducer => bp_proposals.push((p.num_tokens, p.id)),
+            Proposal::ChunkOnlyProducer => cop_proposals.push((p.num_tokens, p.id)),
+            Proposal::Delegate(id) => {
+                roles.push(SelectedRole::new(p.id, Some(Role::Delegator(id)), true))
+            }
+            Proposal::StayOut => roles.push(SelectedRole::new(p.id, None, true)),
         }
+    }
+
+    bp_proposals.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap().reverse());
+    cop_proposals.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap().reverse());
+
+    let (num_bps, num_cops) = match params.selection_mode {
+        SelectionMode::TopN => (params.num_block_producers, params.num_chunk_only_producers),
+        SelectionMode::SeatPrice => (
+            num_accepted(&bp_proposals, params.num_block_producers),
+            num_accepted(&cop_proposals, params.num_chunk_only_producers),
+        ),
     };
 
     // Top N proposals become BPs
-    for (_, id) in bp_proposals.iter().take(params.num_block_producers) {
-        let p = participants.get_mut(id).unwrap();
-        assign_role(p, Some(Role::BlockProducer));
+    for (_, id) in bp_proposals.iter().take(num_bps) {
+        roles.push(SelectedRole::new(*id, Some(Role::BlockProducer), false));
     }
     // Top M proposals become COPs
-    for (_, id) in cop_proposals.iter().take(params.num_chunk_only_producers) {
-        let p = participants.get_mut(id).unwrap();
-        assign_role(p, Some(Role::ChunkOnlyProducer));
+    for (_, id) in cop_proposals.iter().take(num_cops) {
+        roles.push(SelectedRole::new(*id, Some(Role::ChunkOnlyProducer), false));
     }
 
-    // All others delegate to someone in the same proposal group as them
+    // All others delegate to someone in the same proposal group as them, or are
+    // rejected if seats are sold at the seat price
     let mut i = 0;
-    for (_, id) in bp_proposals.iter().skip(params.num_block_producers) {
-        let (_, delegating_id) = bp_proposals[i];
-        let p = participants.get_mut(id).unwrap();
-        assign_role(p, Some(Role::Delegator(delegating_id)));
-        i = (i + 1) % params.num_block_producers;
+    for (_, id) in bp_proposals.iter().skip(num_bps) {
+        let new_role = match params.selection_mode {
+            SelectionMode::TopN => Some(Role::Delegator(bp_proposals[i].1)),
+            SelectionMode::SeatPrice => None,
+        };
+        roles.push(SelectedRole::new(*id, new_role, false));
+        i = (i + 1) % num_bps.max(1);
     }
     i = 0;
-    for (_, id) in cop_proposals.iter().skip(params.num_chunk_only_producers) {
-        let (_, delegating_id) = cop_proposals[i];
-        let p = participants.get_mut(id).unwrap();
-        assign_role(p, Some(Role::Delegator(delegating_id)));
-        i = (i + 1) % params.num_chunk_only_producers;
+    for (_, id) in cop_proposals.iter().skip(num_cops) {
+        let new_role = match params.selection_mode {
+            SelectionMode::TopN => Some(Role::Delegator(cop_proposals[i].1)),
+            SelectionMode::SeatPrice => None,
+        };
+        roles.push(SelectedRole::new(*id, new_role, false));
+        i = (i + 1) % num_cops.max(1);
+    }
+
+    // Delegations to participants which will not be producers would earn nothing, so
+    // `dangling_delegation_policy` applies to them as if the delegatee had disappeared.
+    let is_producer = |role: &Option<Role>| {
+        matches!(
+            role,
+            Some(Role::BlockProducer) | Some(Role::ChunkOnlyProducer)
+        )
+    };
+    let producers: HashSet<Id> = roles
+        .iter()
+        .filter(|r| is_producer(&r.role))
+        .map(|r| r.id)
+        .collect();
+    let best = match params.dangling_delegation_policy {
+        DanglingDelegationPolicy::BestProducer => {
+            let largest = |role: Role| {
+                roles
+                    .iter()
+                    .filter(|r| r.role == Some(role))
+                    .map(|r| (participants[&r.id].num_tokens, r.id))
+                    .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap().then(b.1.cmp(&a.1)))
+                    .map(|(_, id)| id)
+            };
+            Market {
+                largest_block_producer: largest(Role::BlockProducer),
+                largest_chunk_only_producer: largest(Role::ChunkOnlyProducer),
+                ..market
+            }
+            .best_producer()
+        }
+        _ => None,
+    };
+    for selected in roles.iter_mut() {
+        if let Some(Role::Delegator(delegatee_id)) = selected.role {
+            if !producers.contains(&delegatee_id) {
+                selected.role = best.map(Role::Delegator);
+                selected.chosen = false;
+            }
+        }
+    }
+    Ok(roles)
+}
+
+// Gives the participants their selected roles. Participants which have disappeared since
+// the selection are skipped, and delegations to them are dropped. Participants which were
+// just `kicked_out` do not get a seat. Participants which chose to move their stake to
+// another producer (or out of staking) unstake it and have no role while it is locked;
+// roles assigned by the selection take effect without unstaking.
+fn activate_roles<T: EventConsumer>(
+    participants: &mut ParticipantMap,
+    params: &Params,
+    roles: &[SelectedRole],
+    kicked_out: &[Id],
+    time: usize,
+    events: &mut T,
+) -> Result<(), SimError> {
+    for selected in roles.iter() {
+        let new_role = match selected.role {
+            Some(Role::BlockProducer) | Some(Role::ChunkOnlyProducer)
+                if kicked_out.contains(&selected.id) =>
+            {
+                None
+            }
+            Some(Role::Delegator(delegatee_id)) if !participants.contains_key(&delegatee_id) => {
+                None
+            }
+            new_role => new_role,
+        };
+        let p = match participants.get_mut(&selected.id) {
+            Some(p) => p,
+            None => continue,
+        };
+        // all of its tokens may have been unstaked since the selection
+        let new_role = if p.num_tokens > 0.0 { new_role } else { None };
+        if p.role != new_role {
+            let old_location = stake_location(p.id, p.role);
+            let unstake = params.unstake_delay > 0
+                && selected.chosen
+                && old_location.is_some()
+                && old_location != stake_location(p.id, new_role);
+            p.role = if unstake { None } else { new_role };
+            events.push(Event {
+                time,
+                info: event::Info::RoleChange {
+                    participant_id: p.id,
+                    new_role: p.role,
+                },
+            })?;
+            if unstake {
+                let amount = p.num_tokens;
+                p.unstake(amount, false, params, time, events)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The producer the tokens of a participant with `role` are staked with: itself, or its
+// delegatee.
+fn stake_location(id: Id, role: Option<Role>) -> Option<Id> {
+    match role {
+        Some(Role::Delegator(delegatee_id)) => Some(delegatee_id),
+        Some(Role::BlockProducer) | Some(Role::ChunkOnlyProducer) => Some(id),
+        None => None,
+    }
+}
+
+// Assigns every COP to a shard such that the stake (including delegations) of the shards
+// is balanced: COPs are placed one by one, largest first, into the lightest shard.
+fn assign_shards<T: EventConsumer>(
+    participants: &mut ParticipantMap,
+    params: &Params,
+    time: usize,
+    events: &mut T,
+) -> Result<(), SimError> {
+    let mut cops: HashMap<Id, f64> = HashMap::new();
+    for p in participants.values() {
+        match p.role {
+            Some(Role::ChunkOnlyProducer) => {
+                *cops.entry(p.id).or_insert(0f64) += p.num_tokens;
+            }
+            Some(Role::Delegator(id))
+                if participants.get(&id).and_then(|d| d.role) == Some(Role::ChunkOnlyProducer) =>
+            {
+                *cops.entry(id).or_insert(0f64) += p.num_tokens;
+            }
+            _ => (),
+        }
     }
+    let mut cops: Vec<(f64, Id)> = cops.into_iter().map(|(id, stake)| (stake, id)).collect();
+    cops.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap().then(a.1.cmp(&b.1)));
+
+    let mut shard_stakes = vec![0f64; params.num_shards];
+    for (stake, id) in cops {
+        let mut shard = 0;
+        for (s, shard_stake) in shard_stakes.iter().enumerate() {
+            if *shard_stake < shard_stakes[shard] {
+                shard = s;
+            }
+        }
+        shard_stakes[shard] += stake;
+        let p = participants
+            .get_mut(&id)
+            .ok_or(SimError::MissingParticipant {
+                time,
+                participant_id: id,
+            })?;
+        if p.shard != shard {
+            p.shard = shard;
+            events.push(Event {
+                time,
+                info: event::Info::ShardAssigned {
+                    participant_id: id,
+                    shard,
+                },
+            })?;
+        }
+    }
+    Ok(())
+}
+
+// Number of proposals (sorted by decreasing stake) at or above the seat price.
+fn num_accepted(proposals: &[(f64, Id)], num_seats: usize) -> usize {
+    let stakes: Vec<f64> = proposals.iter().map(|(stake, _)| *stake).collect();
+    match seat_price(&stakes, num_seats) {
+        Some(price) => proposals
+            .iter()
+            .take_while(|(stake, _)| *stake >= price)
+            .count(),
+        None => 0,
+    }
+}
+
+// Largest price at which `stakes` buy at least `num_seats` seats, where each stake buys
+// `stake / price` seats rounded down. `None` if there is no positive stake.
+fn seat_price(stakes: &[f64], num_seats: usize) -> Option<f64> {
+    let total: f64 = stakes.iter().filter(|s| **s > 0.0).sum();
+    if total <= 0.0 || num_seats == 0 {
+        return None;
+    }
+    let seats = |price: f64| -> f64 { stakes.iter().map(|s| (s / price).floor()).sum() };
+    // each stake loses less than one seat to rounding, so `low` always buys enough
+    // seats; `high` is above every stake so it buys none
+    let mut low = total / ((num_seats + stakes.len()) as f64);
+    let mut high = 2.0 * stakes.iter().cloned().fold(0f64, f64::max);
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        if mid <= low || mid >= high {
+            break;
+        }
+        if seats(mid) >= num_seats as f64 {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Some(low)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{update_roles, update_token_amounts, Params, Participant};
-    use crate::event::{self, Event, EventAccumulator};
+    use super::{
+        activate_roles, assign_shards, num_accepted, redelegate, seat_price, select_roles,
+        settle_balances, settle_rewards, slash, update_token_amounts, withdraw_unstaked,
+        Participant, ParticipantMap, SelectedRole, Simulation,
+    };
+    use crate::error::SimError;
+    use crate::event::{self, Event, EventAccumulator, EventBlackHole, Stats, StatsAccumulator};
     use crate::id::{Id, IdGenerator};
+    use crate::params::{DanglingDelegationPolicy, Misbehaviour, Params, SelectionMode};
+    use crate::population::InitialStakes;
     use crate::role::Role;
+    use crate::strategy::ParticipantStrategy;
+    use crate::supply::RewardModel;
     use rand::SeedableRng;
-    use std::collections::hash_map::DefaultHasher;
-    use std::collections::HashMap;
-    use std::hash::BuildHasherDefault;
-
-    type BuildDefaultHasher = BuildHasherDefault<DefaultHasher>;
 
     #[test]
     fn test_update_token_amounts() {
         let mut id_gen = IdGenerator::default();
         let mut events = EventAccumulator::default();
-        let stakes = vec![5000.0, 2000.0, 1000.0, 100.0, 10.0];
+        let stakes = [5000.0, 2000.0, 1000.0, 100.0, 10.0];
 
         let params = Params {
             num_block_producers: 1,
@@ -534,45 +1601,71 @@ mod tests {
             block_producer_reward_fraction: 0.6,
             block_producer_delegation_fee: 0.15,
             chunk_only_producer_delegation_fee: 0.05,
+            ..Default::default()
         };
 
-        let mut participants = HashMap::new();
+        let mut participants = ParticipantMap::new();
         let bp = Participant {
             id: id_gen.next(),
             num_tokens: stakes[0],
             role: Some(Role::BlockProducer),
+            shard: 0,
+            liquid_tokens: 0.0,
+            unstaking: Vec::new(),
             most_recent_stake_change: 0.0,
             expected_stake_change_on_switch: 0.0,
+            reliability: 1.0,
+            strategy: ParticipantStrategy::default(),
         };
         let cop = Participant {
             id: id_gen.next(),
             num_tokens: stakes[1],
             role: Some(Role::ChunkOnlyProducer),
+            shard: 0,
+            liquid_tokens: 0.0,
+            unstaking: Vec::new(),
             most_recent_stake_change: 0.0,
             expected_stake_change_on_switch: 0.0,
+            reliability: 1.0,
+            strategy: ParticipantStrategy::default(),
         };
         let delegator = Participant {
             id: id_gen.next(),
             num_tokens: stakes[2],
             role: Some(Role::Delegator(cop.id)),
+            shard: 0,
+            liquid_tokens: 0.0,
+            unstaking: Vec::new(),
             most_recent_stake_change: 0.0,
             expected_stake_change_on_switch: 0.0,
+            reliability: 1.0,
+            strategy: ParticipantStrategy::default(),
         };
         participants.insert(delegator.id, delegator);
         let delegator = Participant {
             id: id_gen.next(),
             num_tokens: stakes[3],
             role: Some(Role::Delegator(cop.id)),
+            shard: 0,
+            liquid_tokens: 0.0,
+            unstaking: Vec::new(),
             most_recent_stake_change: 0.0,
             expected_stake_change_on_switch: 0.0,
+            reliability: 1.0,
+            strategy: ParticipantStrategy::default(),
         };
         participants.insert(delegator.id, delegator);
         let delegator = Participant {
             id: id_gen.next(),
             num_tokens: stakes[4],
             role: Some(Role::Delegator(bp.id)),
+            shard: 0,
+            liquid_tokens: 0.0,
+            unstaking: Vec::new(),
             most_recent_stake_change: 0.0,
             expected_stake_change_on_switch: 0.0,
+            reliability: 1.0,
+            strategy: ParticipantStrategy::default(),
         };
         participants.insert(delegator.id, delegator);
         participants.insert(bp.id, bp);
@@ -581,7 +1674,8 @@ mod tests {
         let total_bp_stake = stakes[0] + stakes[4];
         let total_cop_stake = stakes[1] + stakes[2] + stakes[3];
 
-        update_token_amounts(&mut participants, &params, 0, &mut events);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        update_token_amounts(&mut participants, &params, 0, &mut events, &mut rng).unwrap();
         let mut stake_changes = Vec::with_capacity(stakes.len());
         for e in events.events {
             if let event::Info::StakeChange {
@@ -594,7 +1688,7 @@ mod tests {
                 panic!("Unexpected event: {:?}", e);
             }
         }
-        stake_changes.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        stake_changes.sort_unstable_by_key(|a| a.0);
 
         // bp profit
         assert_float_eq(
@@ -701,10 +1795,9 @@ mod tests {
     fn test_update_roles() {
         let mut id_gen = IdGenerator::default();
         let mut events = EventAccumulator::default();
-        let stakes = vec![5000.0, 4000.0, 3000.0, 2000.0, 1000.0, 500.0, 100.0, 10.0];
+        let stakes = [5000.0, 4000.0, 3000.0, 2000.0, 1000.0, 500.0, 100.0, 10.0];
 
-        // Do not use RandomState for hasher so test is deterministic
-        let mut participants = HashMap::<Id, Participant, BuildDefaultHasher>::default();
+        let mut participants = ParticipantMap::default();
         for s in stakes.iter() {
             let p = Participant::new(&mut id_gen, *s);
             participants.insert(p.id, p);
@@ -719,25 +1812,28 @@ mod tests {
             block_producer_reward_fraction: 0.6,
             block_producer_delegation_fee: 0.15,
             chunk_only_producer_delegation_fee: 0.05,
+            ..Default::default()
         };
 
         // seed rng so test is deterministic
         let mut rng = rand::rngs::StdRng::seed_from_u64(7);
-        update_roles(&mut participants, &params, 0, &mut events, &mut rng);
+        let roles = select_roles(&mut participants, &params, 0, &mut events, &mut rng).unwrap();
+        activate_roles(&mut participants, &params, &roles, &[], 0, &mut events).unwrap();
         sort_events_by_id(&mut events.events);
         // Top params.num_block_producers BP proposals are taken as BPs, others delegate to a BP
         // Top params.num_chunk_only_producers COP proposals are taken as COPS, others delegate to a COP
         let expected_roles = vec![
+            Role::ChunkOnlyProducer,
             Role::ChunkOnlyProducer,
             Role::BlockProducer,
             Role::BlockProducer,
-            Role::ChunkOnlyProducer,
+            Role::Delegator(Id::explicit(2)),
             Role::Delegator(Id::explicit(0)),
             Role::Delegator(Id::explicit(3)),
             Role::Delegator(Id::explicit(1)),
-            Role::Delegator(Id::explicit(2)),
         ];
-        for (e, r) in events.events.iter().zip(expected_roles.into_iter()) {
+        assert_eq!(events.events.len(), expected_roles.len());
+        for (e, r) in events.events.iter().zip(expected_roles) {
             if let event::Info::RoleChange { new_role, .. } = e.info {
                 assert_eq!(new_role, Some(r))
             } else {
@@ -746,10 +1842,11 @@ mod tests {
         }
         events.events.clear();
 
-        update_token_amounts(&mut participants, &params, 0, &mut events);
+        update_token_amounts(&mut participants, &params, 0, &mut events, &mut rng).unwrap();
         events.events.clear();
         // BP delegators could make more money by becoming COP delegators, so they switch
-        update_roles(&mut participants, &params, 0, &mut events, &mut rng);
+        let roles = select_roles(&mut participants, &params, 0, &mut events, &mut rng).unwrap();
+        activate_roles(&mut participants, &params, &roles, &[], 0, &mut events).unwrap();
         let expected_roles = vec![
             Role::Delegator(Id::explicit(1)),
             Role::Delegator(Id::explicit(2)),
@@ -764,9 +1861,847 @@ mod tests {
         }
     }
 
-    fn sort_events_by_id(events: &mut Vec<Event>) {
-        fn event_to_id(e: &Event) -> Id {
-            match e.info {
+    #[test]
+    fn test_delegation_to_unselected_producer() {
+        let mut id_gen = IdGenerator::default();
+        let mut participants = ParticipantMap::default();
+        let mut add = |num_tokens: f64, strategy: ParticipantStrategy, role: Option<Role>| {
+            let p = Participant {
+                role,
+                strategy,
+                ..Participant::new(&mut id_gen, num_tokens)
+            };
+            let id = p.id;
+            participants.insert(id, p);
+            id
+        };
+        let large_bp = add(5000.0, ParticipantStrategy::AlwaysBlockProducer, None);
+        let small_bp = add(1000.0, ParticipantStrategy::AlwaysBlockProducer, None);
+        let loyal = add(
+            100.0,
+            ParticipantStrategy::LoyalDelegator,
+            Some(Role::Delegator(small_bp)),
+        );
+
+        // the small BP does not get the only seat, so the loyal delegator cannot stay
+        let expected = [
+            (DanglingDelegationPolicy::Undelegate, None),
+            (
+                DanglingDelegationPolicy::BestProducer,
+                Some(Role::Delegator(large_bp)),
+            ),
+        ];
+        for (policy, role) in expected.iter() {
+            let params = Params {
+                num_block_producers: 1,
+                dangling_delegation_policy: *policy,
+                ..Default::default()
+            };
+            let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+            let roles = select_roles(
+                &mut participants.clone(),
+                &params,
+                0,
+                &mut EventBlackHole,
+                &mut rng,
+            )
+            .unwrap();
+            // assigned by the selection or the policy, so its stake moves without unstaking
+            assert!(roles.contains(&SelectedRole::new(
+                small_bp,
+                Some(Role::Delegator(large_bp)),
+                false
+            )));
+            assert!(roles.contains(&SelectedRole::new(loyal, *role, false)));
+        }
+    }
+
+    #[test]
+    fn test_seat_price() {
+        // 100 / 30 + 50 / 30 + 30 / 30 = 5 seats, any higher price buys only 4
+        let price = seat_price(&[100.0, 50.0, 30.0, 10.0], 5).unwrap();
+        assert!((price - 30.0).abs() < 1e-9, "{}", price);
+        let proposals: Vec<(f64, Id)> = [100.0, 50.0, 30.0, 10.0]
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (*s, Id::explicit(i)))
+            .collect();
+        assert_eq!(num_accepted(&proposals, 5), 3);
+        assert_eq!(num_accepted(&proposals, 1), 1);
+        assert_eq!(num_accepted(&proposals, 100), 4);
+        assert_eq!(seat_price(&[], 5), None);
+
+        // rejected proposals do not become delegators
+        let mut id_gen = IdGenerator::default();
+        let mut participants = ParticipantMap::default();
+        for s in [5000.0, 4000.0, 3000.0, 2000.0, 1000.0, 500.0, 100.0, 10.0].iter() {
+            let p = Participant::new(&mut id_gen, *s);
+            participants.insert(p.id, p);
+        }
+        let params = Params {
+            num_block_producers: 2,
+            num_chunk_only_producers: 2,
+            selection_mode: SelectionMode::SeatPrice,
+            ..Default::default()
+        };
+        let mut events = EventAccumulator::default();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let roles = select_roles(&mut participants, &params, 0, &mut events, &mut rng).unwrap();
+        activate_roles(&mut participants, &params, &roles, &[], 0, &mut events).unwrap();
+        assert!(!events.events.is_empty());
+        assert!(participants
+            .values()
+            .all(|p| !matches!(p.role, Some(Role::Delegator(_)))));
+        let rejected = participants.values().filter(|p| p.role.is_none()).count();
+        assert!(rejected >= participants.len() - 4);
+    }
+
+    #[test]
+    fn test_kickout() {
+        let mut id_gen = IdGenerator::default();
+        let mut events = EventAccumulator::default();
+        let mut participants = ParticipantMap::default();
+        let params = Params {
+            epoch_length: 10,
+            ..Default::default()
+        };
+        let unreliable = Participant {
+            role: Some(Role::BlockProducer),
+            reliability: 0.0,
+            ..Participant::new(&mut id_gen, 1000.0)
+        };
+        let reliable = Participant {
+            role: Some(Role::BlockProducer),
+            ..Participant::new(&mut id_gen, 1000.0)
+        };
+        let delegator = Participant {
+            role: Some(Role::Delegator(unreliable.id)),
+            ..Participant::new(&mut id_gen, 100.0)
+        };
+        let (unreliable_id, reliable_id, delegator_id) = (unreliable.id, reliable.id, delegator.id);
+        participants.insert(unreliable.id, unreliable);
+        participants.insert(reliable.id, reliable);
+        participants.insert(delegator.id, delegator);
+        let initial_participants = participants.clone();
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let (kicked_out, distributed) =
+            update_token_amounts(&mut participants, &params, 10, &mut events, &mut rng).unwrap();
+        assert_eq!(kicked_out, vec![unreliable_id]);
+        assert_eq!(participants[&unreliable_id].role, None);
+        // the kicked out producer pays its costs, and neither it nor its delegators get
+        // rewards; its delegators are undelegated
+        let bp_cost = 10.0 * params.chunk_only_producer_cost * params.block_producer_cost_factor;
+        assert_float_eq(participants[&unreliable_id].num_tokens, 1000.0 - bp_cost);
+        assert_eq!(participants[&delegator_id].num_tokens, 100.0);
+        assert_eq!(participants[&delegator_id].role, None);
+        // only the reliable BP is paid, and there are no COPs
+        let bp_reward = 10.0 * params.total_reward * params.block_producer_reward_fraction;
+        assert_float_eq(distributed, bp_reward * 1000.0 / 2100.0);
+        assert!(events.events.contains(&Event {
+            time: 10,
+            info: event::Info::RoleChange {
+                participant_id: delegator_id,
+                new_role: None,
+            },
+        }));
+        assert_eq!(
+            events.events[0].info,
+            event::Info::ProductionMissed {
+                participant_id: unreliable_id,
+                num_assigned: 10,
+                num_missed: 10,
+            }
+        );
+        assert_eq!(
+            events.events[1].info,
+            event::Info::KickedOut {
+                participant_id: unreliable_id
+            }
+        );
+
+        // it does not get a seat in the next epoch
+        let roles = [SelectedRole::new(
+            unreliable_id,
+            Some(Role::BlockProducer),
+            false,
+        )];
+        activate_roles(
+            &mut participants,
+            &params,
+            &roles,
+            &kicked_out,
+            10,
+            &mut events,
+        )
+        .unwrap();
+        assert_eq!(participants[&unreliable_id].role, None);
+
+        // the delegators follow the dangling delegation policy
+        let params = Params {
+            dangling_delegation_policy: DanglingDelegationPolicy::BestProducer,
+            ..params
+        };
+        let mut participants = initial_participants;
+        update_token_amounts(&mut participants, &params, 10, &mut events, &mut rng).unwrap();
+        assert_eq!(
+            participants[&delegator_id].role,
+            Some(Role::Delegator(reliable_id))
+        );
+    }
+
+    #[test]
+    fn test_shards() {
+        let mut id_gen = IdGenerator::default();
+        let mut events = EventAccumulator::default();
+        let mut participants = ParticipantMap::default();
+        let params = Params {
+            num_shards: 2,
+            shard_weights: vec![3.0, 1.0],
+            ..Default::default()
+        };
+        let bp = Participant {
+            role: Some(Role::BlockProducer),
+            ..Participant::new(&mut id_gen, 1000.0)
+        };
+        participants.insert(bp.id, bp);
+        let mut cop_ids = Vec::new();
+        for stake in [500.0, 400.0, 300.0, 200.0].iter() {
+            let cop = Participant {
+                role: Some(Role::ChunkOnlyProducer),
+                ..Participant::new(&mut id_gen, *stake)
+            };
+            cop_ids.push(cop.id);
+            participants.insert(cop.id, cop);
+        }
+        let delegator = Participant {
+            role: Some(Role::Delegator(cop_ids[3])),
+            ..Participant::new(&mut id_gen, 150.0)
+        };
+        let delegator_id = delegator.id;
+        participants.insert(delegator.id, delegator);
+
+        // largest first into the lightest shard: 500 | 400, 350 (with the delegation) | 300
+        assign_shards(&mut participants, &params, 1, &mut events).unwrap();
+        let shards: Vec<usize> = cop_ids.iter().map(|id| participants[id].shard).collect();
+        assert_eq!(shards, vec![0, 1, 0, 1]);
+        assert_eq!(events.events.len(), 2);
+        assert_eq!(
+            events.events[0].info,
+            event::Info::ShardAssigned {
+                participant_id: cop_ids[1],
+                shard: 1,
+            }
+        );
+
+        // shard 0 gets 3/4 of the COP rewards and its COPs pay 3/2 of the usual cost
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        update_token_amounts(&mut participants, &params, 1, &mut events, &mut rng).unwrap();
+        let cop_reward = params.total_reward * (1.0 - params.block_producer_reward_fraction);
+        let expected = 0.75 * cop_reward * 500.0 / 800.0 - 1.5 * params.chunk_only_producer_cost;
+        assert_float_eq(participants[&cop_ids[0]].num_tokens, 500.0 + expected);
+        let expected =
+            0.25 * cop_reward * 150.0 / 750.0 * (1.0 - params.chunk_only_producer_delegation_fee);
+        assert_float_eq(participants[&delegator_id].num_tokens, 150.0 + expected);
+    }
+
+    #[test]
+    fn test_liquid_balances() {
+        let mut id_gen = IdGenerator::default();
+        let mut events = EventAccumulator::default();
+        let mut participants = ParticipantMap::default();
+        let params = Params {
+            external_yield: 0.01,
+            rebalance_rate: 0.5,
+            epoch_length: 10,
+            ..Default::default()
+        };
+        // staking paid 0.5% per time step, less than the external yield
+        let bp = Participant {
+            role: Some(Role::BlockProducer),
+            liquid_tokens: 1000.0,
+            most_recent_stake_change: 50.0,
+            ..Participant::new(&mut id_gen, 1000.0)
+        };
+        let bp_id = bp.id;
+        participants.insert(bp.id, bp);
+
+        settle_balances(&mut participants, &params, 10, &mut events).unwrap();
+        assert_float_eq(participants[&bp_id].liquid_tokens, 1000.0 + 100.0 + 500.0);
+        assert_float_eq(participants[&bp_id].num_tokens, 500.0);
+        let changes: Vec<f64> = events
+            .events
+            .iter()
+            .filter_map(|e| match e.info {
+                event::Info::LiquidChange { change_amount, .. } => Some(change_amount),
+                event::Info::StakeChange { change_amount, .. } => Some(change_amount),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(changes, vec![100.0, -500.0, 500.0]);
+
+        // tokens without a role earn nothing, so they are moved to the liquid balance;
+        // a producer whose tokens were all slashed does not move anything
+        let mut participants = ParticipantMap::default();
+        let bystander = Participant::new(&mut id_gen, 1000.0);
+        let slashed = Participant {
+            role: Some(Role::BlockProducer),
+            liquid_tokens: 100.0,
+            most_recent_stake_change: 5.0,
+            ..Participant::new(&mut id_gen, 0.0)
+        };
+        let (bystander_id, slashed_id) = (bystander.id, slashed.id);
+        participants.insert(bystander.id, bystander);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let mut events = EventAccumulator::default();
+        update_token_amounts(&mut participants, &params, 10, &mut events, &mut rng).unwrap();
+        assert_eq!(participants[&bystander_id].num_tokens, 1000.0);
+        assert!(events.events.is_empty());
+        participants.insert(slashed.id, slashed);
+        settle_balances(&mut participants, &params, 10, &mut events).unwrap();
+        assert_float_eq(participants[&bystander_id].num_tokens, 500.0);
+        assert_float_eq(participants[&bystander_id].liquid_tokens, 500.0);
+        assert_eq!(participants[&slashed_id].num_tokens, 0.0);
+        assert_float_eq(participants[&slashed_id].liquid_tokens, 110.0);
+        assert_eq!(events.events.len(), 3);
+    }
+
+    #[test]
+    fn test_stake_runs_out() {
+        let mut id_gen = IdGenerator::default();
+        let mut events = EventAccumulator::default();
+        let mut participants = ParticipantMap::default();
+        // the BP costs are larger than the stakes
+        let params = Params {
+            total_reward: 0.0,
+            ..Default::default()
+        };
+        let saver = Participant {
+            role: Some(Role::BlockProducer),
+            liquid_tokens: 100.0,
+            ..Participant::new(&mut id_gen, 10.0)
+        };
+        let spender = Participant {
+            role: Some(Role::BlockProducer),
+            ..Participant::new(&mut id_gen, 10.0)
+        };
+        let delegator = Participant {
+            role: Some(Role::Delegator(saver.id)),
+            ..Participant::new(&mut id_gen, 50.0)
+        };
+        let (saver_id, spender_id, delegator_id) = (saver.id, spender.id, delegator.id);
+        for p in vec![saver, spender, delegator] {
+            participants.insert(p.id, p);
+        }
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        update_token_amounts(&mut participants, &params, 1, &mut events, &mut rng).unwrap();
+
+        // only the participant without any other tokens goes bankrupt
+        assert!(!participants.contains_key(&spender_id));
+        let saver = &participants[&saver_id];
+        assert_eq!((saver.num_tokens, saver.liquid_tokens), (0.0, 100.0));
+        assert_eq!(saver.role, None);
+        assert!(events.events.contains(&Event {
+            time: 1,
+            info: event::Info::StakeChange {
+                participant_id: saver_id,
+                change_amount: -10.0,
+            },
+        }));
+        // its delegators dangle
+        assert_eq!(participants[&delegator_id].role, None);
+    }
+
+    #[test]
+    fn test_unstaking() {
+        let mut id_gen = IdGenerator::default();
+        let mut events = EventAccumulator::default();
+        let mut participants = ParticipantMap::default();
+        let params = Params {
+            unstake_delay: 2,
+            ..Default::default()
+        };
+        let bps: Vec<Participant> = (0..2)
+            .map(|_| Participant {
+                role: Some(Role::BlockProducer),
+                ..Participant::new(&mut id_gen, 1000.0)
+            })
+            .collect();
+        let delegators: Vec<Participant> = (0..2)
+            .map(|_| Participant {
+                role: Some(Role::Delegator(bps[0].id)),
+                ..Participant::new(&mut id_gen, 100.0)
+            })
+            .collect();
+        let (bp_id, delegator_id, assigned_id) = (bps[1].id, delegators[0].id, delegators[1].id);
+        for p in bps.into_iter().chain(delegators) {
+            participants.insert(p.id, p);
+        }
+
+        // moving to another BP by choice locks the tokens, and the delegator has no role
+        // meanwhile
+        let roles = [
+            SelectedRole::new(delegator_id, Some(Role::Delegator(bp_id)), true),
+            SelectedRole::new(assigned_id, Some(Role::Delegator(bp_id)), false),
+            SelectedRole::new(bp_id, Some(Role::ChunkOnlyProducer), false),
+        ];
+        activate_roles(&mut participants, &params, &roles, &[], 1, &mut events).unwrap();
+        assert_eq!(participants[&delegator_id].role, None);
+        assert_eq!(participants[&delegator_id].num_tokens, 0.0);
+        assert!(events.events.contains(&Event {
+            time: 1,
+            info: event::Info::UnstakeRequested {
+                participant_id: delegator_id,
+                amount: 100.0,
+            },
+        }));
+        // a delegation assigned by the selection and a producer switching roles keep
+        // their stake
+        assert_eq!(
+            participants[&assigned_id].role,
+            Some(Role::Delegator(bp_id))
+        );
+        assert_eq!(participants[&assigned_id].num_tokens, 100.0);
+        assert_eq!(participants[&bp_id].role, Some(Role::ChunkOnlyProducer));
+        assert_eq!(participants[&bp_id].num_tokens, 1000.0);
+
+        withdraw_unstaked(&mut participants, 2, &mut events).unwrap();
+        assert_eq!(participants[&delegator_id].num_tokens, 0.0);
+        withdraw_unstaked(&mut participants, 3, &mut events).unwrap();
+        assert_eq!(participants[&delegator_id].num_tokens, 100.0);
+        assert!(participants[&delegator_id].unstaking.is_empty());
+        assert!(matches!(
+            events.events.last().unwrap().info,
+            event::Info::StakeChange {
+                change_amount, ..
+            } if change_amount == 100.0
+        ));
+
+        // tokens unstaked by rebalancing are locked for the full delay as well, and earn
+        // no external yield meanwhile
+        let params = Params {
+            unstake_delay: 1,
+            external_yield: 0.01,
+            rebalance_rate: 1.0,
+            epoch_length: 10,
+            ..Default::default()
+        };
+        let mut participants = ParticipantMap::default();
+        // staking paid nothing, and the tokens of the bystander were never staked
+        let bp = Participant {
+            role: Some(Role::BlockProducer),
+            ..Participant::new(&mut id_gen, 1000.0)
+        };
+        let bystander = Participant::new(&mut id_gen, 1000.0);
+        let (bp_id, bystander_id) = (bp.id, bystander.id);
+        participants.insert(bp.id, bp);
+        participants.insert(bystander.id, bystander);
+        settle_balances(&mut participants, &params, 10, &mut events).unwrap();
+        assert_eq!(participants[&bp_id].num_tokens, 0.0);
+        assert_eq!(participants[&bp_id].liquid_tokens, 0.0);
+        assert_eq!(participants[&bp_id].unstaking.len(), 1);
+        assert_eq!(participants[&bystander_id].num_tokens, 0.0);
+        assert_eq!(participants[&bystander_id].liquid_tokens, 1000.0);
+        assert!(participants[&bystander_id].unstaking.is_empty());
+        settle_balances(&mut participants, &params, 20, &mut events).unwrap();
+        assert_eq!(participants[&bp_id].liquid_tokens, 1000.0);
+        assert!(participants[&bp_id].unstaking.is_empty());
+    }
+
+    #[test]
+    fn test_stake_in_transit_is_bounded() {
+        let params = Params {
+            unstake_delay: 3,
+            seed: Some(3),
+            ..Default::default()
+        };
+        let mut stats = StatsAccumulator::default();
+        Simulation::new(params)
+            .unwrap()
+            .run(100, &mut stats)
+            .unwrap();
+        let columns = Stats::columns();
+        let column = |name: &str| columns.iter().position(|c| c == name).unwrap();
+        let (bp, cop, in_transit) = (
+            column("total_bp_stake"),
+            column("total_cop_stake"),
+            column("stake_in_transit"),
+        );
+        for s in stats.sampled_series(100, 10).iter().skip(1) {
+            let values = s.values();
+            let staked = values[bp] + values[cop];
+            assert!(
+                values[in_transit] < 0.1 * staked,
+                "{} in transit, {} staked",
+                values[in_transit],
+                staked
+            );
+        }
+    }
+
+    #[test]
+    fn test_slashing() {
+        let mut id_gen = IdGenerator::default();
+        let mut events = EventAccumulator::default();
+        let mut participants = ParticipantMap::default();
+        let bp = Participant {
+            role: Some(Role::BlockProducer),
+            ..Participant::new(&mut id_gen, 1000.0)
+        };
+        let delegator = Participant {
+            role: Some(Role::Delegator(bp.id)),
+            ..Participant::new(&mut id_gen, 100.0)
+        };
+        let cop = Participant {
+            role: Some(Role::ChunkOnlyProducer),
+            ..Participant::new(&mut id_gen, 1000.0)
+        };
+        let (bp_id, delegator_id, cop_id) = (bp.id, delegator.id, cop.id);
+        participants.insert(bp.id, bp);
+        participants.insert(delegator.id, delegator);
+        participants.insert(cop.id, cop);
+        let params = Params {
+            epoch_length: 10,
+            scripted_misbehaviour: vec![Misbehaviour {
+                time: 15,
+                participant_id: bp_id,
+            }],
+            slash_fraction: 0.1,
+            ..Default::default()
+        };
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        slash(&mut participants, &params, 10, &mut events, &mut rng).unwrap();
+        assert!(events.events.is_empty());
+        slash(&mut participants, &params, 20, &mut events, &mut rng).unwrap();
+        assert_eq!(
+            events.events,
+            vec![Event {
+                time: 20,
+                info: event::Info::Slashed {
+                    participant_id: bp_id,
+                    amount: 100.0,
+                    delegator_amounts: vec![(delegator_id, 10.0)],
+                },
+            }]
+        );
+        assert_eq!(participants[&bp_id].num_tokens, 900.0);
+        assert_eq!(participants[&delegator_id].num_tokens, 90.0);
+        assert_eq!(participants[&cop_id].num_tokens, 1000.0);
+
+        // a producer which is kicked out for the same epoch is still slashed, with its
+        // delegators
+        participants.get_mut(&bp_id).unwrap().reliability = 0.0;
+        let mut events = EventAccumulator::default();
+        let (kicked_out, _, slashed) =
+            settle_rewards(&mut participants, &params, 20, &mut events, &mut rng).unwrap();
+        assert_eq!(kicked_out, vec![bp_id]);
+        assert_float_eq(slashed, 99.0);
+        assert!(events.events.contains(&Event {
+            time: 20,
+            info: event::Info::Slashed {
+                participant_id: bp_id,
+                amount: 90.0,
+                delegator_amounts: vec![(delegator_id, 9.0)],
+            },
+        }));
+    }
+
+    #[test]
+    fn test_missing_delegatee_is_an_error() {
+        let mut id_gen = IdGenerator::default();
+        let mut events = EventAccumulator::default();
+        let mut participants = ParticipantMap::default();
+        let bp = Participant {
+            role: Some(Role::BlockProducer),
+            ..Participant::new(&mut id_gen, 1000.0)
+        };
+        let missing_id = id_gen.next();
+        let delegator = Participant {
+            role: Some(Role::Delegator(missing_id)),
+            ..Participant::new(&mut id_gen, 100.0)
+        };
+        let delegator_id = delegator.id;
+        participants.insert(bp.id, bp);
+        participants.insert(delegator.id, delegator);
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let result = update_token_amounts(
+            &mut participants,
+            &Params::default(),
+            3,
+            &mut events,
+            &mut rng,
+        );
+        assert_eq!(
+            result,
+            Err(SimError::MissingDelegatee {
+                time: 3,
+                participant_id: delegator_id,
+                delegatee_id: missing_id,
+            })
+        );
+    }
+
+    #[test]
+    fn test_dangling_delegations() {
+        let mut id_gen = IdGenerator::default();
+        let mut participants = ParticipantMap::default();
+        let mut add = |num_tokens: f64, role: Option<Role>| {
+            let p = Participant {
+                role,
+                ..Participant::new(&mut id_gen, num_tokens)
+            };
+            let id = p.id;
+            participants.insert(id, p);
+            id
+        };
+        let split_bp = add(1000.0, Some(Role::BlockProducer));
+        add(3000.0, Some(Role::BlockProducer));
+        let cop = add(500.0, Some(Role::ChunkOnlyProducer));
+        let d1 = add(100.0, Some(Role::Delegator(split_bp)));
+        let d2 = add(100.0, Some(Role::Delegator(split_bp)));
+        // the BP is split into two halves
+        let s1 = add(500.0, Some(Role::BlockProducer));
+        let s2 = add(500.0, Some(Role::BlockProducer));
+        participants.swap_remove(&split_bp);
+
+        let expected = [
+            (DanglingDelegationPolicy::Undelegate, [None, None]),
+            (
+                DanglingDelegationPolicy::FollowSuccessor,
+                [Some(Role::Delegator(s1)), Some(Role::Delegator(s2))],
+            ),
+            // COP delegators earn more per token since there is much less COP stake
+            (
+                DanglingDelegationPolicy::BestProducer,
+                [Some(Role::Delegator(cop)), Some(Role::Delegator(cop))],
+            ),
+        ];
+        for (policy, roles) in expected.iter() {
+            let params = Params {
+                dangling_delegation_policy: *policy,
+                ..Default::default()
+            };
+            let mut participants = participants.clone();
+            let mut events = EventAccumulator::default();
+            redelegate(
+                &mut participants,
+                &params,
+                1,
+                &mut events,
+                &[split_bp],
+                &[s1, s2],
+            )
+            .unwrap();
+            assert_eq!(participants[&d1].role, roles[0]);
+            assert_eq!(participants[&d2].role, roles[1]);
+            assert_eq!(events.events.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_seeded_runs_are_deterministic() {
+        let params = || Params {
+            num_block_producers: 2,
+            num_chunk_only_producers: 2,
+            chunk_only_producer_cost: 5.0,
+            block_producer_cost_factor: 7.0,
+            total_reward: 3000.0,
+            block_producer_reward_fraction: 0.6,
+            block_producer_delegation_fee: 0.15,
+            chunk_only_producer_delegation_fee: 0.05,
+            seed: Some(42),
+            initial_stakes: InitialStakes::LogNormal {
+                num_participants: 20,
+                mu: 7.0,
+                sigma: 1.0,
+            },
+            ..Default::default()
+        };
+
+        let mut events_1 = EventAccumulator::default();
+        Simulation::new(params())
+            .unwrap()
+            .run(200, &mut events_1)
+            .unwrap();
+        let mut events_2 = EventAccumulator::default();
+        Simulation::new(params())
+            .unwrap()
+            .run(200, &mut events_2)
+            .unwrap();
+
+        assert!(!events_1.events.is_empty());
+        assert_eq!(events_1.events, events_2.events);
+    }
+
+    #[test]
+    fn test_epochs() {
+        let params = Params {
+            num_block_producers: 2,
+            num_chunk_only_producers: 2,
+            seed: Some(42),
+            initial_stakes: InitialStakes::LogNormal {
+                num_participants: 20,
+                mu: 7.0,
+                sigma: 1.0,
+            },
+            epoch_length: 10,
+            activation_delay: 2,
+            ..Default::default()
+        };
+        let mut events = EventAccumulator::default();
+        Simulation::new(params)
+            .unwrap()
+            .run(35, &mut events)
+            .unwrap();
+
+        let times = |is_kind: fn(&event::Info) -> bool| -> Vec<usize> {
+            events
+                .events
+                .iter()
+                .filter(|e| e.time > 0 && is_kind(&e.info))
+                .map(|e| e.time)
+                .collect()
+        };
+        // rewards are paid at the end of every epoch
+        let stake_changes = times(|info| matches!(info, event::Info::StakeChange { .. }));
+        assert!(stake_changes.contains(&30));
+        assert!(stake_changes.iter().all(|t| t % 10 == 0));
+        // the initial participants have no role, the roles proposed in epoch 1 (ending at
+        // time 10) take effect in epoch 3 (starting after time 20)
+        let role_changes = times(|info| matches!(info, event::Info::RoleChange { .. }));
+        assert_eq!(role_changes.first(), Some(&20));
+    }
+
+    #[test]
+    fn test_inflation() {
+        let params = Params {
+            seed: Some(3),
+            reward_model: RewardModel::Inflation {
+                initial_supply: 1e6,
+                inflation_rate: 0.001,
+                treasury_fraction: 0.1,
+                fee_revenue: 10.0,
+                fee_burn_fraction: 0.5,
+                target_staking_ratio: 0.0,
+            },
+            epoch_length: 5,
+            ..Default::default()
+        };
+        let mut events = EventAccumulator::default();
+        Simulation::new(params)
+            .unwrap()
+            .run(20, &mut events)
+            .unwrap();
+
+        let supply: Vec<(f64, f64)> = events
+            .events
+            .iter()
+            .filter_map(|e| match e.info {
+                event::Info::SupplyChange {
+                    total_supply,
+                    minted,
+                    ..
+                } => Some((total_supply, minted)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(supply.len(), 3);
+        // each epoch mints from the supply at its start; half of the 50 tokens in fees burn
+        assert_float_eq(supply[0].1, 1000.0);
+        assert_float_eq(supply[0].0, 1e6 + 1000.0 - 25.0);
+        assert_float_eq(supply[1].1, 0.001 * supply[0].0);
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let params = Params {
+            num_block_producers: 3,
+            num_chunk_only_producers: 4,
+            seed: Some(5),
+            initial_stakes: InitialStakes::LogNormal {
+                num_participants: 20,
+                mu: 7.0,
+                sigma: 1.0,
+            },
+            ..Default::default()
+        };
+        let mut uninterrupted = EventAccumulator::default();
+        Simulation::new(params.clone())
+            .unwrap()
+            .run(200, &mut uninterrupted)
+            .unwrap();
+
+        let mut simulation = Simulation::new(params).unwrap();
+        simulation.run(100, &mut EventBlackHole).unwrap();
+        let checkpoint = serde_json::to_string(&simulation).unwrap();
+        let mut resumed: Simulation = serde_json::from_str(&checkpoint).unwrap();
+        assert_eq!(resumed.time(), 100);
+        let mut events = EventAccumulator::default();
+        resumed.run(200, &mut events).unwrap();
+
+        // the participants at the checkpoint are reported at time 99
+        assert!(events.events.iter().all(|e| e.time >= 99));
+        let after_checkpoint = |events: &[Event]| -> Vec<Event> {
+            events.iter().filter(|e| e.time >= 100).cloned().collect()
+        };
+        assert!(!after_checkpoint(&events.events).is_empty());
+        assert_eq!(
+            after_checkpoint(&events.events),
+            after_checkpoint(&uninterrupted.events)
+        );
+    }
+
+    #[test]
+    fn test_resume_during_schedule() {
+        // new participants keep a growing part of their tokens liquid, which only changes
+        // at the end of an epoch
+        let params: Params = serde_json::from_value(serde_json::json!({
+            "num_block_producers": 3,
+            "num_chunk_only_producers": 4,
+            "seed": 11,
+            "initial_stakes": {"distribution": "uniform", "num_participants": 20,
+                               "min_stake": 100.0, "max_stake": 1000.0},
+            "epoch_length": 10,
+            "schedules": {
+                "liquid_fraction": {"kind": "ramp", "start_time": 0, "end_time": 100,
+                                    "from": 0.0, "to": 0.5}
+            }
+        }))
+        .unwrap();
+        let mut uninterrupted = EventAccumulator::default();
+        Simulation::new(params.clone())
+            .unwrap()
+            .run(60, &mut uninterrupted)
+            .unwrap();
+
+        let mut simulation = Simulation::new(params).unwrap();
+        simulation.run(35, &mut EventBlackHole).unwrap();
+        assert_eq!(
+            simulation
+                .scheduled_params
+                .as_ref()
+                .unwrap()
+                .liquid_fraction,
+            0.15
+        );
+        let checkpoint = serde_json::to_string(&simulation).unwrap();
+        let mut resumed: Simulation = serde_json::from_str(&checkpoint).unwrap();
+        let mut events = EventAccumulator::default();
+        resumed.run(60, &mut events).unwrap();
+        let after_checkpoint = |events: &[Event]| -> Vec<Event> {
+            events.iter().filter(|e| e.time >= 35).cloned().collect()
+        };
+        assert_eq!(
+            after_checkpoint(&events.events),
+            after_checkpoint(&uninterrupted.events)
+        );
+    }
+
+    fn sort_events_by_id(events: &mut [Event]) {
+        fn event_to_id(e: &Event) -> Option<Id> {
+            let id = match e.info {
                 event::Info::ParticipantCreated { participant_id, .. } => participant_id,
                 event::Info::StakeChange { participant_id, .. } => participant_id,
                 event::Info::RoleChange { participant_id, .. } => participant_id,
@@ -775,9 +2710,19 @@ mod tests {
                 } => new_participant_id,
                 event::Info::ParticipantSplit { participant_id, .. } => participant_id,
                 event::Info::ParticipantBankrupt { participant_id, .. } => participant_id,
-            }
+                event::Info::ProductionMissed { participant_id, .. } => participant_id,
+                event::Info::KickedOut { participant_id } => participant_id,
+                event::Info::Slashed { participant_id, .. } => participant_id,
+                event::Info::ValueEstimates { participant_id, .. } => participant_id,
+                event::Info::ShardAssigned { participant_id, .. } => participant_id,
+                event::Info::LiquidChange { participant_id, .. } => participant_id,
+                event::Info::UnstakeRequested { participant_id, .. } => participant_id,
+                event::Info::Withdrawn { participant_id, .. } => participant_id,
+                event::Info::SupplyChange { .. } => return None,
+            };
+            Some(id)
         }
-        events.sort_unstable_by(|a, b| event_to_id(a).cmp(&event_to_id(b)))
+        events.sort_unstable_by_key(event_to_id)
     }
 
     // Don't use == for floats to avoid false positives from rounding error
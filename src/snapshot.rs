@@ -0,0 +1,186 @@
+use crate::id::Id;
+use crate::role::Role;
+use crate::sim::InitialParticipant;
+
+use serde::Deserialize;
+
+use std::io;
+use std::path::Path;
+
+// Number of yoctoNEAR in one NEAR; stakes in the simulation are measured in NEAR.
+const YOCTO_PER_NEAR: f64 = 1e24;
+
+// Either the bare `validators` RPC result or the full JSON-RPC response wrapping it.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SnapshotFile {
+    Response { result: ValidatorsResult },
+    Result(ValidatorsResult),
+}
+
+#[derive(Deserialize)]
+struct ValidatorsResult {
+    current_validators: Vec<ValidatorInfo>,
+}
+
+#[derive(Deserialize)]
+struct ValidatorInfo {
+    account_id: String,
+    // Total stake of the validator (including delegations) in yoctoNEAR.
+    stake: String,
+    #[serde(default)]
+    num_expected_blocks: u64,
+    #[serde(default)]
+    num_expected_chunks: u64,
+    // Explicit role; if omitted it is inferred from the expected blocks and chunks.
+    #[serde(default)]
+    role: Option<SnapshotRole>,
+    // Accounts delegating to this validator's staking pool. Their stake is
+    // part of the validator's `stake`.
+    #[serde(default)]
+    delegators: Vec<DelegatorInfo>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum SnapshotRole {
+    BlockProducer,
+    ChunkOnlyProducer,
+}
+
+#[derive(Deserialize)]
+struct DelegatorInfo {
+    account_id: String,
+    stake: String,
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<InitialParticipant>> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+// Validators are listed first (in the order they appear in the file), followed by
+// all delegators. Delegators refer to their validator by index, which is the `Id`
+// the validator is given by `Simulation::new`.
+fn parse(contents: &str) -> io::Result<Vec<InitialParticipant>> {
+    let validators = match serde_json::from_str(contents)? {
+        SnapshotFile::Response { result } => result.current_validators,
+        SnapshotFile::Result(result) => result.current_validators,
+    };
+
+    let mut participants = Vec::with_capacity(validators.len());
+    let mut delegators = Vec::new();
+    for (idx, v) in validators.iter().enumerate() {
+        let role = match v.role {
+            Some(SnapshotRole::BlockProducer) => Role::BlockProducer,
+            Some(SnapshotRole::ChunkOnlyProducer) => Role::ChunkOnlyProducer,
+            None if v.num_expected_blocks == 0 && v.num_expected_chunks > 0 => {
+                Role::ChunkOnlyProducer
+            }
+            None => Role::BlockProducer,
+        };
+        let mut own_stake = parse_stake(&v.account_id, &v.stake)?;
+        for d in v.delegators.iter() {
+            let stake = parse_stake(&d.account_id, &d.stake)?;
+            own_stake -= stake;
+            delegators.push(InitialParticipant {
+                num_tokens: stake,
+                role: Some(Role::Delegator(Id::explicit(idx))),
+            });
+        }
+        if own_stake <= 0.0 {
+            return Err(invalid_data(format!(
+                "delegated stake of {} exceeds its total stake",
+                v.account_id
+            )));
+        }
+        participants.push(InitialParticipant {
+            num_tokens: own_stake,
+            role: Some(role),
+        });
+    }
+    participants.extend(delegators);
+
+    Ok(participants)
+}
+
+fn parse_stake(account_id: &str, stake: &str) -> io::Result<f64> {
+    let yocto: u128 = stake
+        .parse()
+        .map_err(|_| invalid_data(format!("invalid stake for {}: {:?}", account_id, stake)))?;
+    if yocto == 0 {
+        return Err(invalid_data(format!("{} has zero stake", account_id)));
+    }
+    Ok((yocto as f64) / YOCTO_PER_NEAR)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::id::Id;
+    use crate::role::Role;
+
+    #[test]
+    fn test_parse_snapshot() {
+        let snapshot = r#"{
+            "jsonrpc": "2.0",
+            "id": "dontcare",
+            "result": {
+                "current_validators": [
+                    {
+                        "account_id": "bp.poolv1.near",
+                        "stake": "5000000000000000000000000000",
+                        "num_expected_blocks": 100,
+                        "num_expected_chunks": 400,
+                        "delegators": [
+                            { "account_id": "alice.near", "stake": "1000000000000000000000000000" },
+                            { "account_id": "bob.near", "stake": "500000000000000000000000000" }
+                        ]
+                    },
+                    {
+                        "account_id": "cop.poolv1.near",
+                        "stake": "2000000000000000000000000000",
+                        "num_expected_blocks": 0,
+                        "num_expected_chunks": 400,
+                        "delegators": [
+                            { "account_id": "carol.near", "stake": "100000000000000000000000000" }
+                        ]
+                    }
+                ],
+                "next_validators": []
+            }
+        }"#;
+        let participants = parse(snapshot).unwrap();
+        let summary: Vec<(f64, Option<Role>)> = participants
+            .iter()
+            .map(|p| (p.num_tokens, p.role))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (3500.0, Some(Role::BlockProducer)),
+                (1900.0, Some(Role::ChunkOnlyProducer)),
+                (1000.0, Some(Role::Delegator(Id::explicit(0)))),
+                (500.0, Some(Role::Delegator(Id::explicit(0)))),
+                (100.0, Some(Role::Delegator(Id::explicit(1)))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_snapshot_errors() {
+        let over_delegated = r#"{ "current_validators": [ {
+            "account_id": "pool.near",
+            "stake": "1000",
+            "delegators": [ { "account_id": "alice.near", "stake": "1000" } ]
+        } ] }"#;
+        assert!(parse(over_delegated).is_err());
+
+        let bad_stake =
+            r#"{ "current_validators": [ { "account_id": "pool.near", "stake": "1e24" } ] }"#;
+        assert!(parse(bad_stake).is_err());
+    }
+}
@@ -0,0 +1,678 @@
+use crate::id::Id;
+use crate::role::Role;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// What a participant knows about itself when proposing its role for the next epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub id: Id,
+    pub num_tokens: f64,
+    pub role: Option<Role>,
+    // role of the delegatee if the participant is a delegator
+    pub delegatee_role: Option<Role>,
+    // stake change in the last epoch
+    pub most_recent_stake_change: f64,
+    // stake change in the last epoch if it had been in the other producer role
+    pub expected_stake_change_on_switch: f64,
+}
+
+impl Observation {
+    // Producer role the participant takes part in, either itself or through its delegatee.
+    pub fn producer_role(&self) -> Option<Role> {
+        match self.role {
+            Some(Role::Delegator(_)) => self.delegatee_role,
+            role => role,
+        }
+    }
+}
+
+// What a participant knows about all other participants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Market {
+    // tokens paid per delegated token and time step to BP (COP) delegators; `None` if
+    // nothing is staked in the role, so that there is no rate to compare
+    pub block_producer_delegator_rate: Option<f64>,
+    pub chunk_only_producer_delegator_rate: Option<f64>,
+    // producers with the largest own stake, if there are any
+    pub largest_block_producer: Option<Id>,
+    pub largest_chunk_only_producer: Option<Id>,
+    // tokens paid per token and time step outside staking
+    pub external_yield: f64,
+}
+
+impl Market {
+    // The producer role whose delegators currently earn the most per token, BP on a tie,
+    // with that rate. `None` if nothing is staked in either role.
+    pub fn best_delegator_rate(&self) -> Option<(Role, f64)> {
+        match (
+            self.block_producer_delegator_rate,
+            self.chunk_only_producer_delegator_rate,
+        ) {
+            (Some(bp_rate), Some(cop_rate)) if cop_rate > bp_rate => {
+                Some((Role::ChunkOnlyProducer, cop_rate))
+            }
+            (Some(bp_rate), _) => Some((Role::BlockProducer, bp_rate)),
+            (None, Some(cop_rate)) => Some((Role::ChunkOnlyProducer, cop_rate)),
+            (None, None) => None,
+        }
+    }
+
+    // The producer whose delegators currently earn the most per token. Every producer in
+    // a role pays delegators the same, so ties are broken by the largest own stake.
+    pub fn best_producer(&self) -> Option<Id> {
+        match self.best_delegator_rate() {
+            Some((Role::ChunkOnlyProducer, _)) => self
+                .largest_chunk_only_producer
+                .or(self.largest_block_producer),
+            _ => self
+                .largest_block_producer
+                .or(self.largest_chunk_only_producer),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Proposal {
+    BlockProducer,
+    ChunkOnlyProducer,
+    Delegate(Id),
+    StayOut,
+}
+
+// How a participant chooses its role at the end of every epoch.
+pub trait Strategy {
+    fn propose<R: Rng>(
+        &mut self,
+        observation: &Observation,
+        market: &Market,
+        rng: &mut R,
+    ) -> Proposal;
+}
+
+// Keeps its producer role, but switches with 5% chance if the other role would have paid
+// more in the last epoch, and 1% otherwise. Without a role it picks one at random.
+pub struct Stochastic;
+
+impl Strategy for Stochastic {
+    fn propose<R: Rng>(
+        &mut self,
+        observation: &Observation,
+        _market: &Market,
+        rng: &mut R,
+    ) -> Proposal {
+        let probability_to_switch =
+            if observation.most_recent_stake_change > observation.expected_stake_change_on_switch {
+                0.01f64
+            } else {
+                0.05f64
+            };
+        let x: f64 = rng.gen();
+        match observation.producer_role() {
+            Some(Role::BlockProducer) if x < probability_to_switch => Proposal::ChunkOnlyProducer,
+            Some(Role::BlockProducer) => Proposal::BlockProducer,
+            Some(Role::ChunkOnlyProducer) if x < probability_to_switch => Proposal::BlockProducer,
+            Some(Role::ChunkOnlyProducer) => Proposal::ChunkOnlyProducer,
+            None | Some(Role::Delegator(_)) => {
+                if rng.gen() {
+                    Proposal::BlockProducer
+                } else {
+                    Proposal::ChunkOnlyProducer
+                }
+            }
+        }
+    }
+}
+
+// Always takes the producer role which paid more in the last epoch. Without a role it
+// picks the role whose delegators currently earn more per token, unless the external
+// yield is higher still. Before anything is staked it becomes a BP.
+pub struct Greedy;
+
+impl Strategy for Greedy {
+    fn propose<R: Rng>(
+        &mut self,
+        observation: &Observation,
+        market: &Market,
+        _rng: &mut R,
+    ) -> Proposal {
+        let stay =
+            observation.most_recent_stake_change >= observation.expected_stake_change_on_switch;
+        match observation.producer_role() {
+            Some(Role::BlockProducer) if stay => Proposal::BlockProducer,
+            Some(Role::BlockProducer) => Proposal::ChunkOnlyProducer,
+            Some(Role::ChunkOnlyProducer) if stay => Proposal::ChunkOnlyProducer,
+            Some(Role::ChunkOnlyProducer) => Proposal::BlockProducer,
+            None | Some(Role::Delegator(_)) => match market.best_delegator_rate() {
+                Some((_, rate)) if market.external_yield > rate => Proposal::StayOut,
+                Some((Role::ChunkOnlyProducer, _)) => Proposal::ChunkOnlyProducer,
+                _ => Proposal::BlockProducer,
+            },
+        }
+    }
+}
+
+pub struct AlwaysBlockProducer;
+
+impl Strategy for AlwaysBlockProducer {
+    fn propose<R: Rng>(&mut self, _: &Observation, _: &Market, _: &mut R) -> Proposal {
+        Proposal::BlockProducer
+    }
+}
+
+pub struct AlwaysChunkOnlyProducer;
+
+impl Strategy for AlwaysChunkOnlyProducer {
+    fn propose<R: Rng>(&mut self, _: &Observation, _: &Market, _: &mut R) -> Proposal {
+        Proposal::ChunkOnlyProducer
+    }
+}
+
+// Never moves its delegation. Until it has a delegatee it delegates to the best producer.
+pub struct LoyalDelegator;
+
+impl Strategy for LoyalDelegator {
+    fn propose<R: Rng>(
+        &mut self,
+        observation: &Observation,
+        market: &Market,
+        _rng: &mut R,
+    ) -> Proposal {
+        match observation.role {
+            Some(Role::Delegator(id)) => Proposal::Delegate(id),
+            _ => market
+                .best_producer()
+                .filter(|id| *id != observation.id)
+                .map_or(Proposal::StayOut, Proposal::Delegate),
+        }
+    }
+}
+
+// The strategy carried by a participant.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StrategyKind {
+    Stochastic,
+    Greedy,
+    AlwaysBlockProducer,
+    AlwaysChunkOnlyProducer,
+    LoyalDelegator,
+    Bandit,
+}
+
+impl StrategyKind {
+    pub const ALL: [StrategyKind; 6] = [
+        Self::Stochastic,
+        Self::Greedy,
+        Self::AlwaysBlockProducer,
+        Self::AlwaysChunkOnlyProducer,
+        Self::LoyalDelegator,
+        Self::Bandit,
+    ];
+
+    // Same as the serialized name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Stochastic => "stochastic",
+            Self::Greedy => "greedy",
+            Self::AlwaysBlockProducer => "always_block_producer",
+            Self::AlwaysChunkOnlyProducer => "always_chunk_only_producer",
+            Self::LoyalDelegator => "loyal_delegator",
+            Self::Bandit => "bandit",
+        }
+    }
+
+    // Position in `ALL`.
+    pub fn index(&self) -> usize {
+        Self::ALL.iter().position(|s| s == self).unwrap()
+    }
+}
+
+impl Default for StrategyKind {
+    fn default() -> Self {
+        Self::Stochastic
+    }
+}
+
+// The strategy of a participant, together with what it has learnt.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParticipantStrategy {
+    Stochastic,
+    Greedy,
+    AlwaysBlockProducer,
+    AlwaysChunkOnlyProducer,
+    LoyalDelegator,
+    Bandit(Bandit),
+}
+
+impl ParticipantStrategy {
+    // A participant following `kind` which has not learnt anything yet.
+    pub fn new(kind: StrategyKind, learning_rate: f64, exploration: f64) -> Self {
+        match kind {
+            StrategyKind::Stochastic => Self::Stochastic,
+            StrategyKind::Greedy => Self::Greedy,
+            StrategyKind::AlwaysBlockProducer => Self::AlwaysBlockProducer,
+            StrategyKind::AlwaysChunkOnlyProducer => Self::AlwaysChunkOnlyProducer,
+            StrategyKind::LoyalDelegator => Self::LoyalDelegator,
+            StrategyKind::Bandit => Self::Bandit(Bandit::new(learning_rate, exploration)),
+        }
+    }
+
+    pub fn kind(&self) -> StrategyKind {
+        match self {
+            Self::Stochastic => StrategyKind::Stochastic,
+            Self::Greedy => StrategyKind::Greedy,
+            Self::AlwaysBlockProducer => StrategyKind::AlwaysBlockProducer,
+            Self::AlwaysChunkOnlyProducer => StrategyKind::AlwaysChunkOnlyProducer,
+            Self::LoyalDelegator => StrategyKind::LoyalDelegator,
+            Self::Bandit(_) => StrategyKind::Bandit,
+        }
+    }
+}
+
+impl Default for ParticipantStrategy {
+    fn default() -> Self {
+        Self::Stochastic
+    }
+}
+
+impl Strategy for ParticipantStrategy {
+    fn propose<R: Rng>(
+        &mut self,
+        observation: &Observation,
+        market: &Market,
+        rng: &mut R,
+    ) -> Proposal {
+        match self {
+            Self::Stochastic => Stochastic.propose(observation, market, rng),
+            Self::Greedy => Greedy.propose(observation, market, rng),
+            Self::AlwaysBlockProducer => AlwaysBlockProducer.propose(observation, market, rng),
+            Self::AlwaysChunkOnlyProducer => {
+                AlwaysChunkOnlyProducer.propose(observation, market, rng)
+            }
+            Self::LoyalDelegator => LoyalDelegator.propose(observation, market, rng),
+            Self::Bandit(bandit) => bandit.propose(observation, market, rng),
+        }
+    }
+}
+
+// The choices of a `Bandit`. Delegators delegate to the largest producer in the role.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Arm {
+    BlockProducer,
+    ChunkOnlyProducer,
+    BlockProducerDelegator,
+    ChunkOnlyProducerDelegator,
+}
+
+impl Arm {
+    pub const ALL: [Arm; 4] = [
+        Self::BlockProducer,
+        Self::ChunkOnlyProducer,
+        Self::BlockProducerDelegator,
+        Self::ChunkOnlyProducerDelegator,
+    ];
+
+    // Arm which led to the participant's current role, if it has one.
+    fn of(observation: &Observation) -> Option<Arm> {
+        match (observation.role, observation.delegatee_role) {
+            (Some(Role::BlockProducer), _) => Some(Self::BlockProducer),
+            (Some(Role::ChunkOnlyProducer), _) => Some(Self::ChunkOnlyProducer),
+            (Some(Role::Delegator(_)), Some(Role::BlockProducer)) => {
+                Some(Self::BlockProducerDelegator)
+            }
+            (Some(Role::Delegator(_)), Some(Role::ChunkOnlyProducer)) => {
+                Some(Self::ChunkOnlyProducerDelegator)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Epsilon-greedy multi-armed bandit. At the end of every epoch it updates the value of
+// the arm matching its current role with the return per token of the last epoch, then
+// picks a random arm with probability `exploration` and the most valuable arm otherwise.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bandit {
+    learning_rate: f64,
+    exploration: f64,
+    // estimated return per token, in the order of `Arm::ALL`
+    values: [f64; 4],
+}
+
+impl Bandit {
+    pub fn new(learning_rate: f64, exploration: f64) -> Self {
+        Self {
+            learning_rate,
+            exploration,
+            values: [0.0; 4],
+        }
+    }
+
+    pub fn values(&self) -> Vec<(Arm, f64)> {
+        Arm::ALL
+            .iter()
+            .copied()
+            .zip(self.values.iter().copied())
+            .collect()
+    }
+}
+
+impl Strategy for Bandit {
+    fn propose<R: Rng>(
+        &mut self,
+        observation: &Observation,
+        market: &Market,
+        rng: &mut R,
+    ) -> Proposal {
+        if let Some(arm) = Arm::of(observation) {
+            if observation.num_tokens > 0.0 {
+                let reward = observation.most_recent_stake_change / observation.num_tokens;
+                let value = &mut self.values[arm as usize];
+                *value += self.learning_rate * (reward - *value);
+            }
+        }
+
+        let x: f64 = rng.gen();
+        let arm = if x < self.exploration {
+            Arm::ALL[rng.gen_range(0..Arm::ALL.len())]
+        } else {
+            let mut best = 0;
+            for (i, value) in self.values.iter().enumerate() {
+                if *value > self.values[best] {
+                    best = i;
+                }
+            }
+            Arm::ALL[best]
+        };
+        let delegate = |delegatee: Option<Id>| {
+            delegatee
+                .filter(|id| *id != observation.id)
+                .map_or(Proposal::StayOut, Proposal::Delegate)
+        };
+        match arm {
+            Arm::BlockProducer => Proposal::BlockProducer,
+            Arm::ChunkOnlyProducer => Proposal::ChunkOnlyProducer,
+            Arm::BlockProducerDelegator => delegate(market.largest_block_producer),
+            Arm::ChunkOnlyProducerDelegator => delegate(market.largest_chunk_only_producer),
+        }
+    }
+}
+
+// Part of the population following `strategy`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StrategyShare {
+    pub strategy: StrategyKind,
+    // Fraction of the initial stake held by participants with this strategy. It is also
+    // the probability for a new participant to get this strategy.
+    pub stake_share: f64,
+    // Give the strategy to the initial participants with the largest stakes instead of
+    // random ones.
+    #[serde(default)]
+    pub largest_first: bool,
+}
+
+// Strategies of the participants with `stakes`, following `mix`. Stake not covered by
+// the mix follows `default`. A participant only joins a share if that brings the share
+// closer to its `stake_share`, so a single large participant cannot push a share far past
+// it. Does not use `rng` if the mix is empty.
+pub fn assign_strategies<R: Rng>(
+    stakes: &[f64],
+    mix: &[StrategyShare],
+    default: StrategyKind,
+    rng: &mut R,
+) -> Vec<StrategyKind> {
+    let mut strategies = vec![default; stakes.len()];
+    if mix.is_empty() {
+        return strategies;
+    }
+    let total: f64 = stakes.iter().sum();
+    let mut largest: Vec<usize> = (0..stakes.len()).collect();
+    largest.sort_by(|a, b| stakes[*b].partial_cmp(&stakes[*a]).unwrap().then(a.cmp(b)));
+    let mut shuffled: Vec<usize> = (0..stakes.len()).collect();
+    shuffled.shuffle(rng);
+
+    // the largest participants are handed out first, so random shares cannot take them
+    let mut assigned = vec![false; stakes.len()];
+    let shares = mix
+        .iter()
+        .filter(|s| s.largest_first)
+        .chain(mix.iter().filter(|s| !s.largest_first));
+    for share in shares {
+        let order = if share.largest_first {
+            &largest
+        } else {
+            &shuffled
+        };
+        let target = share.stake_share * total;
+        let mut stake = 0f64;
+        for i in order.iter().copied() {
+            if stake >= target {
+                break;
+            }
+            if !assigned[i] && stake + stakes[i] / 2.0 <= target {
+                assigned[i] = true;
+                strategies[i] = share.strategy;
+                stake += stakes[i];
+            }
+        }
+    }
+    strategies
+}
+
+// Strategy of a new participant. Does not use `rng` if the mix is empty.
+pub fn draw_strategy<R: Rng>(
+    mix: &[StrategyShare],
+    default: StrategyKind,
+    rng: &mut R,
+) -> StrategyKind {
+    if mix.is_empty() {
+        return default;
+    }
+    let mut x: f64 = rng.gen();
+    for share in mix {
+        if x < share.stake_share {
+            return share.strategy;
+        }
+        x -= share.stake_share;
+    }
+    default
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        assign_strategies, Arm, Bandit, Market, Observation, ParticipantStrategy, Proposal,
+        Strategy, StrategyKind, StrategyShare,
+    };
+    use crate::id::Id;
+    use crate::role::Role;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_strategies() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let market = Market {
+            block_producer_delegator_rate: Some(0.1),
+            chunk_only_producer_delegator_rate: Some(0.2),
+            largest_block_producer: Some(Id::explicit(1)),
+            largest_chunk_only_producer: Some(Id::explicit(2)),
+            external_yield: 0.0,
+        };
+        let cop_delegator = Observation {
+            id: Id::explicit(0),
+            num_tokens: 100.0,
+            role: Some(Role::Delegator(Id::explicit(2))),
+            delegatee_role: Some(Role::ChunkOnlyProducer),
+            most_recent_stake_change: 1.0,
+            expected_stake_change_on_switch: 2.0,
+        };
+        let newcomer = Observation {
+            role: None,
+            delegatee_role: None,
+            ..cop_delegator.clone()
+        };
+        let mut propose = |kind: StrategyKind, observation: &Observation| {
+            ParticipantStrategy::new(kind, 0.1, 0.1).propose(observation, &market, &mut rng)
+        };
+
+        // a BP would have paid the delegator more
+        assert_eq!(
+            propose(StrategyKind::Greedy, &cop_delegator),
+            Proposal::BlockProducer
+        );
+        // COP delegators earn more per token
+        assert_eq!(
+            propose(StrategyKind::Greedy, &newcomer),
+            Proposal::ChunkOnlyProducer
+        );
+        assert_eq!(
+            propose(StrategyKind::LoyalDelegator, &cop_delegator),
+            Proposal::Delegate(Id::explicit(2))
+        );
+        assert_eq!(
+            propose(StrategyKind::LoyalDelegator, &newcomer),
+            Proposal::Delegate(Id::explicit(2))
+        );
+        assert_eq!(
+            propose(StrategyKind::AlwaysBlockProducer, &newcomer),
+            Proposal::BlockProducer
+        );
+        assert_eq!(
+            propose(StrategyKind::AlwaysChunkOnlyProducer, &newcomer),
+            Proposal::ChunkOnlyProducer
+        );
+
+        // tokens earn more outside staking
+        let market = Market {
+            external_yield: 0.3,
+            ..market.clone()
+        };
+        assert_eq!(
+            ParticipantStrategy::Greedy.propose(&newcomer, &market, &mut rng),
+            Proposal::StayOut
+        );
+
+        // a role without stake has no rate, so the other one is picked
+        let market = Market {
+            chunk_only_producer_delegator_rate: None,
+            largest_chunk_only_producer: None,
+            ..market
+        };
+        assert_eq!(
+            ParticipantStrategy::Greedy.propose(&newcomer, &market, &mut rng),
+            Proposal::StayOut
+        );
+        let market = Market {
+            block_producer_delegator_rate: None,
+            chunk_only_producer_delegator_rate: Some(0.2),
+            external_yield: 0.0,
+            ..market
+        };
+        assert_eq!(
+            ParticipantStrategy::Greedy.propose(&newcomer, &market, &mut rng),
+            Proposal::ChunkOnlyProducer
+        );
+        assert_eq!(market.best_producer(), Some(Id::explicit(1)));
+    }
+
+    #[test]
+    fn test_assign_strategies() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let stakes = [100.0, 10.0, 50.0, 20.0, 20.0];
+        let mix = vec![
+            StrategyShare {
+                strategy: StrategyKind::Greedy,
+                stake_share: 0.5,
+                largest_first: true,
+            },
+            StrategyShare {
+                strategy: StrategyKind::LoyalDelegator,
+                stake_share: 0.1,
+                largest_first: false,
+            },
+        ];
+        let strategies = assign_strategies(&stakes, &mix, StrategyKind::Stochastic, &mut rng);
+        // the largest participant alone holds half of the stake
+        assert_eq!(strategies[0], StrategyKind::Greedy);
+        let stake = |kind| -> f64 {
+            stakes
+                .iter()
+                .zip(strategies.iter())
+                .filter(|(_, s)| **s == kind)
+                .map(|(stake, _)| stake)
+                .sum()
+        };
+        assert_eq!(stake(StrategyKind::Greedy), 100.0);
+        // random participants are added until they hold at least 10% of the stake, but
+        // the one with 50 would overshoot
+        let loyal_stake = stake(StrategyKind::LoyalDelegator);
+        assert!((20.0..=30.0).contains(&loyal_stake), "{}", loyal_stake);
+        assert!(stake(StrategyKind::Stochastic) > 0.0);
+
+        // a whale is never given a small share
+        let whale_stakes = [1000.0, 10.0, 10.0, 10.0];
+        let mix = vec![StrategyShare {
+            strategy: StrategyKind::Greedy,
+            stake_share: 0.01,
+            largest_first: false,
+        }];
+        for _ in 0..10 {
+            let strategies =
+                assign_strategies(&whale_stakes, &mix, StrategyKind::Stochastic, &mut rng);
+            assert_eq!(strategies[0], StrategyKind::Stochastic);
+        }
+
+        assert_eq!(
+            assign_strategies(&stakes, &[], StrategyKind::Greedy, &mut rng),
+            vec![StrategyKind::Greedy; 5]
+        );
+    }
+
+    #[test]
+    fn test_bandit() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let market = Market {
+            block_producer_delegator_rate: Some(0.1),
+            chunk_only_producer_delegator_rate: Some(0.2),
+            largest_block_producer: Some(Id::explicit(1)),
+            largest_chunk_only_producer: Some(Id::explicit(2)),
+            external_yield: 0.0,
+        };
+        let cop_delegator = Observation {
+            id: Id::explicit(0),
+            num_tokens: 100.0,
+            role: Some(Role::Delegator(Id::explicit(2))),
+            delegatee_role: Some(Role::ChunkOnlyProducer),
+            most_recent_stake_change: 10.0,
+            expected_stake_change_on_switch: 0.0,
+        };
+        // without exploration the bandit sticks to the only arm which paid anything
+        let mut bandit = Bandit::new(0.5, 0.0);
+        for _ in 0..3 {
+            assert_eq!(
+                bandit.propose(&cop_delegator, &market, &mut rng),
+                Proposal::Delegate(Id::explicit(2))
+            );
+        }
+        let values = bandit.values();
+        assert_eq!(values[3].0, Arm::ChunkOnlyProducerDelegator);
+        assert!((values[3].1 - 0.0875).abs() < 1e-12);
+        assert!(values[..3].iter().all(|(_, v)| *v == 0.0));
+
+        // only participants with the bandit strategy carry a bandit
+        for kind in StrategyKind::ALL.iter() {
+            let strategy = ParticipantStrategy::new(*kind, 0.5, 0.0);
+            assert_eq!(strategy.kind(), *kind);
+            assert_eq!(
+                matches!(strategy, ParticipantStrategy::Bandit(_)),
+                *kind == StrategyKind::Bandit
+            );
+        }
+    }
+}
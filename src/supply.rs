@@ -0,0 +1,147 @@
+use crate::params::Validator;
+
+use serde::{Deserialize, Serialize};
+
+// Where the rewards paid to the producers (and their delegators) come from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum RewardModel {
+    // `total_reward` is paid every time step, independent of the token supply.
+    Fixed,
+    // At the end of every epoch `inflation_rate` of the supply is minted, of which
+    // `treasury_fraction` goes to the treasury. Of the transaction fees (`fee_revenue`
+    // per time step) `fee_burn_fraction` is burned. The remaining minted tokens and fees
+    // form the reward pool, which replaces `total_reward`; the part of it which is not
+    // paid out (e.g. for missed blocks) goes to the treasury as well.
+    Inflation {
+        // Total supply at the start, including tokens held outside the simulation.
+        initial_supply: f64,
+        inflation_rate: f64,
+        treasury_fraction: f64,
+        fee_revenue: f64,
+        fee_burn_fraction: f64,
+        // If more than this fraction of the supply is staked, the minted tokens are scaled
+        // down by `target_staking_ratio / staking_ratio`, so that the return per staked
+        // token falls faster and staking is pushed back towards the target. 0 disables it.
+        #[serde(default)]
+        target_staking_ratio: f64,
+    },
+}
+
+impl Default for RewardModel {
+    fn default() -> Self {
+        Self::Fixed
+    }
+}
+
+// Tokens created and destroyed in one epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpochSupply {
+    pub minted: f64,
+    pub treasury: f64,
+    pub burned: f64,
+    pub reward_pool: f64,
+}
+
+impl RewardModel {
+    pub fn initial_supply(&self) -> Option<f64> {
+        match self {
+            Self::Fixed => None,
+            Self::Inflation { initial_supply, .. } => Some(*initial_supply),
+        }
+    }
+
+    // Token flows of an epoch starting with `supply` tokens, of which `staked` are staked.
+    // `None` if the rewards do not depend on the supply.
+    pub fn epoch(&self, supply: f64, staked: f64, epoch_length: usize) -> Option<EpochSupply> {
+        match self {
+            Self::Fixed => None,
+            Self::Inflation {
+                inflation_rate,
+                treasury_fraction,
+                fee_revenue,
+                fee_burn_fraction,
+                target_staking_ratio,
+                ..
+            } => {
+                let target_stake = target_staking_ratio * supply;
+                let scale = if *target_staking_ratio > 0.0 && staked > target_stake {
+                    target_stake / staked
+                } else {
+                    1.0
+                };
+                let minted = inflation_rate * supply * scale;
+                let treasury = treasury_fraction * minted;
+                let fees = fee_revenue * (epoch_length as f64);
+                let burned = fee_burn_fraction * fees;
+                Some(EpochSupply {
+                    minted,
+                    treasury,
+                    burned,
+                    reward_pool: minted - treasury + fees - burned,
+                })
+            }
+        }
+    }
+
+    pub fn validate(&self, v: &mut Validator) {
+        if let Self::Inflation {
+            initial_supply,
+            inflation_rate,
+            treasury_fraction,
+            fee_revenue,
+            fee_burn_fraction,
+            target_staking_ratio,
+        } = self
+        {
+            v.non_negative(*initial_supply, "reward_model.initial_supply");
+            v.non_negative(*inflation_rate, "reward_model.inflation_rate");
+            v.fraction(*treasury_fraction, "reward_model.treasury_fraction");
+            v.non_negative(*fee_revenue, "reward_model.fee_revenue");
+            v.fraction(*fee_burn_fraction, "reward_model.fee_burn_fraction");
+            v.fraction(*target_staking_ratio, "reward_model.target_staking_ratio");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EpochSupply, RewardModel};
+
+    #[test]
+    fn test_inflation() {
+        let model: RewardModel = serde_json::from_str(
+            r#"{"kind": "inflation", "initial_supply": 1000.0, "inflation_rate": 0.01,
+                "treasury_fraction": 0.1, "fee_revenue": 2.0, "fee_burn_fraction": 0.7}"#,
+        )
+        .unwrap();
+        assert_eq!(model.initial_supply(), Some(1000.0));
+        let epoch = model.epoch(2000.0, 1500.0, 5).unwrap();
+        assert_eq!(
+            epoch,
+            EpochSupply {
+                minted: 20.0,
+                treasury: 2.0,
+                burned: 7.0,
+                reward_pool: 21.0,
+            }
+        );
+        assert_eq!(RewardModel::default().epoch(2000.0, 1500.0, 5), None);
+    }
+
+    #[test]
+    fn test_target_staking_ratio() {
+        let model = RewardModel::Inflation {
+            initial_supply: 1000.0,
+            inflation_rate: 0.01,
+            treasury_fraction: 0.0,
+            fee_revenue: 0.0,
+            fee_burn_fraction: 0.0,
+            target_staking_ratio: 0.5,
+        };
+        // below the target the full inflation is minted
+        assert_eq!(model.epoch(2000.0, 800.0, 5).unwrap().minted, 20.0);
+        // with 80% staked only 5/8 of it
+        assert_eq!(model.epoch(2000.0, 1600.0, 5).unwrap().minted, 12.5);
+    }
+}
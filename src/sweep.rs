@@ -0,0 +1,279 @@
+use crate::event::StatsAccumulator;
+use crate::params::{self, Params};
+use crate::runner;
+use crate::sim::{ParticipantCounts, Simulation};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+// Grid of `Params` to run the simulation for. Each entry of `grid` maps a field of
+// `Params` to the values it takes; every combination of values is run once per seed.
+// Nested fields are addressed with dots, e.g. `initial_stakes.num_participants`.
+#[derive(Deserialize, Debug)]
+pub struct SweepSpec {
+    pub base: Params,
+    pub grid: BTreeMap<String, Axis>,
+    // Seeds to run every grid point with. If empty the seed from `base` is used.
+    #[serde(default)]
+    pub seeds: Vec<u64>,
+    pub duration: usize,
+    // Relative tolerance used to decide when the stake fraction has converged.
+    #[serde(default = "default_convergence_tolerance")]
+    pub convergence_tolerance: f64,
+}
+
+fn default_convergence_tolerance() -> f64 {
+    0.01
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Axis {
+    // `steps` evenly spaced values from `start` to `end` (inclusive).
+    Range { start: f64, end: f64, steps: usize },
+    Values(Vec<Value>),
+}
+
+impl Axis {
+    fn values(&self) -> Vec<Value> {
+        match self {
+            Self::Values(values) => values.clone(),
+            Self::Range { start, end, steps } => (0..*steps)
+                .map(|i| {
+                    let x = if *steps > 1 {
+                        start + (end - start) * (i as f64) / ((steps - 1) as f64)
+                    } else {
+                        *start
+                    };
+                    // integer fields (e.g. `num_block_producers`) cannot be deserialized from floats
+                    if x.fract() == 0.0 && x.abs() < (i64::MAX as f64) {
+                        Value::from(x as i64)
+                    } else {
+                        Value::from(x)
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+// One run of the sweep: the grid values (in the order of the grid fields), the seed,
+// and the resulting summary.
+#[derive(Debug, Clone)]
+pub struct SweepRow {
+    pub point: Vec<Value>,
+    pub seed: Option<u64>,
+    pub stake_fraction: f64,
+    pub survivors: ParticipantCounts,
+    pub convergence_time: Option<usize>,
+}
+
+impl SweepSpec {
+    // All `Params` in the sweep, together with the grid values they were created from.
+    pub fn runs(&self) -> io::Result<Vec<(Vec<Value>, Params)>> {
+        let base = serde_json::to_value(&self.base)?;
+        let fields: Vec<&String> = self.grid.keys().collect();
+        let seeds: Vec<Option<u64>> = if self.seeds.is_empty() {
+            vec![self.base.seed]
+        } else {
+            self.seeds.iter().map(|s| Some(*s)).collect()
+        };
+
+        let mut points: Vec<Vec<Value>> = vec![Vec::new()];
+        for axis in self.grid.values() {
+            let values = axis.values();
+            points = points
+                .into_iter()
+                .flat_map(|point| {
+                    values.iter().map(move |v| {
+                        let mut point = point.clone();
+                        point.push(v.clone());
+                        point
+                    })
+                })
+                .collect();
+        }
+
+        let mut runs = Vec::with_capacity(points.len() * seeds.len());
+        for point in points {
+            let mut value = base.clone();
+            for (field, v) in fields.iter().zip(point.iter()) {
+                params::set_field(&mut value, field, v.clone())?;
+            }
+            for seed in seeds.iter() {
+                params::set_field(&mut value, "seed", serde_json::to_value(seed)?)?;
+                let params = serde_json::from_value(value.clone())?;
+                runs.push((point.clone(), params));
+            }
+        }
+        Ok(runs)
+    }
+
+    pub fn run(&self, num_threads: usize) -> io::Result<Vec<SweepRow>> {
+        let duration = self.duration;
+        let convergence_tolerance = self.convergence_tolerance;
+        runner::run_parallel(self.runs()?, num_threads, move |(point, params)| {
+            run_point(point, params, duration, convergence_tolerance)
+        })
+        .into_iter()
+        .collect()
+    }
+
+    pub fn write_rows<P: AsRef<Path>>(&self, rows: &[SweepRow], file_name: P) -> io::Result<()> {
+        let mut file = File::create(file_name)?;
+        let mut header: Vec<String> = self.grid.keys().map(|k| csv_field(k)).collect();
+        header.push("seed,stake_fraction,num_block_producers,num_chunk_only_producers,num_delegators,num_participants,convergence_time".to_string());
+        file.write_all(format!("{}\n", header.join(",")).as_bytes())?;
+        for row in rows {
+            let mut fields: Vec<String> = row
+                .point
+                .iter()
+                .map(|v| csv_field(&v.to_string()))
+                .collect();
+            fields.push(format!(
+                "{},{},{},{},{},{},{}",
+                optional(row.seed),
+                row.stake_fraction,
+                row.survivors.block_producers,
+                row.survivors.chunk_only_producers,
+                row.survivors.delegators,
+                row.survivors.total,
+                optional(row.convergence_time),
+            ));
+            file.write_all(format!("{}\n", fields.join(",")).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn write_rows_json<P: AsRef<Path>>(
+        &self,
+        rows: &[SweepRow],
+        file_name: P,
+    ) -> io::Result<()> {
+        let rows: Vec<Value> = rows
+            .iter()
+            .map(|row| {
+                let point: serde_json::Map<String, Value> = self
+                    .grid
+                    .keys()
+                    .cloned()
+                    .zip(row.point.iter().cloned())
+                    .collect();
+                serde_json::json!({
+                    "point": point,
+                    "seed": row.seed,
+                    "stake_fraction": row.stake_fraction,
+                    "num_block_producers": row.survivors.block_producers,
+                    "num_chunk_only_producers": row.survivors.chunk_only_producers,
+                    "num_delegators": row.survivors.delegators,
+                    "num_participants": row.survivors.total,
+                    "convergence_time": row.convergence_time,
+                })
+            })
+            .collect();
+        serde_json::to_writer(File::create(file_name)?, &rows)?;
+        Ok(())
+    }
+}
+
+fn run_point(
+    point: Vec<Value>,
+    params: Params,
+    duration: usize,
+    convergence_tolerance: f64,
+) -> io::Result<SweepRow> {
+    let seed = params.seed;
+    let mut simulation = Simulation::new(params)?;
+    let mut events = StatsAccumulator::default();
+    simulation.run(duration, &mut events)?;
+    Ok(SweepRow {
+        point,
+        seed,
+        stake_fraction: simulation.stake_fraction(),
+        survivors: simulation.participant_counts(),
+        convergence_time: events.convergence_time(convergence_tolerance),
+    })
+}
+
+fn optional<T: ToString>(x: Option<T>) -> String {
+    x.map(|x| x.to_string()).unwrap_or_default()
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Axis, SweepSpec};
+    use crate::population::InitialStakes;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_axis_values() {
+        let range = Axis::Range {
+            start: 0.0,
+            end: 1.0,
+            steps: 5,
+        };
+        assert_eq!(
+            range.values(),
+            vec![json!(0), json!(0.25), json!(0.5), json!(0.75), json!(1)]
+        );
+        let list: Axis = serde_json::from_value(json!(["a", 2])).unwrap();
+        assert_eq!(list.values(), vec![json!("a"), json!(2)]);
+    }
+
+    #[test]
+    fn test_sweep_runs() {
+        let spec: SweepSpec = serde_json::from_value(json!({
+            "base": {
+                "num_block_producers": 2,
+                "num_chunk_only_producers": 2,
+                "chunk_only_producer_cost": 5.0,
+                "block_producer_cost_factor": 7.0,
+                "total_reward": 3000.0,
+                "block_producer_reward_fraction": 0.6,
+                "block_producer_delegation_fee": 0.15,
+                "chunk_only_producer_delegation_fee": 0.05,
+                "initial_stakes": {"distribution": "explicit", "stakes": [5000.0, 3000.0, 100.0, 10.0]}
+            },
+            "grid": {
+                "num_block_producers": {"start": 1, "end": 3, "steps": 3},
+                "initial_stakes.stakes": [[100.0, 200.0], [300.0, 400.0]]
+            },
+            "seeds": [1, 2],
+            "duration": 10
+        }))
+        .unwrap();
+
+        let runs = spec.runs().unwrap();
+        assert_eq!(runs.len(), 3 * 2 * 2);
+        let (point, params) = &runs[11];
+        assert_eq!(point, &vec![json!([300.0, 400.0]), json!(3)]);
+        assert_eq!(params.num_block_producers, 3);
+        assert_eq!(params.seed, Some(2));
+        assert_eq!(
+            params.initial_stakes,
+            InitialStakes::Explicit {
+                stakes: vec![300.0, 400.0]
+            }
+        );
+
+        let rows = spec.run(2).unwrap();
+        assert_eq!(rows.len(), runs.len());
+        assert!(rows.iter().all(|r| r.survivors.total > 0));
+        assert!(rows
+            .iter()
+            .any(|r| r.point[0] == Value::from(vec![100.0, 200.0])));
+    }
+}